@@ -0,0 +1,9 @@
+//! Shorthand for `ai eval`.
+
+fn main() {
+    if let Err(e) = ai::cli::eval(std::env::args().skip(1).collect()) {
+        eprintln!("{}", e);
+        eprintln!("{}", ai::cli::USAGE);
+        std::process::exit(2);
+    }
+}
@@ -0,0 +1,513 @@
+//! Command line of the `ai` binary: `train`, `worker`, `eval`, `play`,
+//! `export`, `pack` and `inspect`.
+
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::configs::Config;
+use crate::dist::{run_worker, Coordinator};
+use crate::engine::{Board, Data};
+use crate::eval::{benchmark, load_population, parse_seeds, Agent, Summary};
+use crate::format::Population;
+use crate::game::Game;
+use crate::metrics::Sink;
+use crate::nn::Network;
+use crate::npz;
+use crate::pack;
+use crate::render::{render, Style};
+use crate::shutdown;
+use crate::world::World;
+
+pub const USAGE: &str = "usage: ai <command> [options]
+
+commands:
+  train    [--config <file>] [--output <population>] [--workers <n>] [--depth <d>]
+           [--population-size <n>] [--seed <s>] [--generations <n>] [--metrics <file.csv|.jsonl>]
+           [--set <key>=<value>]... [--listen <addr>]
+  worker   <addr> [--workers <n>]
+  eval     <model> [--seeds 1..101] [--depth 4] [--agent search|greedy] [--bot <index>]
+           [--workers <n>]
+  play     <model> [--seed <s>] [--depth 4] [--bot <index>] [--board <notation>]
+           [--color] [--unicode]
+  export   <model> --output <file.npz|file> [--bot <index> | --all]
+  pack     <model> [--bot <index>] [--output codingame/src/weights.rs]
+  inspect  <model>
+
+<model> is a population file, a hall of fame (.hof) or an .npz export. export writes
+NumPy for .npz outputs and the population format otherwise. pack writes the weights of the
+CodinGame bot and the points_weight it was trained with, printed when --output is not given. play starts from --board when given, in the
+notation it prints after every move (hex exponents row by row, e.g. 0000/0000/0000/1200). Settings of --config and --set are
+the fields of `Config`, e.g. `selection = tournament:3`. With --listen, train hands the games
+to `ai worker` processes connecting to <addr> (e.g. 0.0.0.0:2048); workers may come and go.
+SIGINT or SIGTERM stops train after the current generation with a final save; a second one exits
+at once.";
+
+/// Parsed `--flag value` pairs and positional arguments.
+#[derive(Debug, Default)]
+pub struct Args {
+    pub positional: Vec<String>,
+    pub values: Vec<(String, String)>,
+    pub switches: Vec<String>,
+}
+
+impl Args {
+    /// `flags` take a value (`--flag v` or `--flag=v`), `switches` do not.
+    pub fn parse(
+        args: impl IntoIterator<Item = String>,
+        flags: &[&str],
+        switches: &[&str],
+    ) -> Result<Self, String> {
+        let mut res = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                if arg.starts_with('-') && arg != "-" {
+                    return Err(format!("unexpected argument {}", arg));
+                }
+                res.positional.push(arg);
+                continue;
+            };
+            let (name, inline) = match name.split_once('=') {
+                Some((name, v)) => (name, Some(v.to_string())),
+                None => (name, None),
+            };
+            if switches.contains(&name) && inline.is_none() {
+                res.switches.push(name.to_string());
+            } else if flags.contains(&name) {
+                let v = match inline {
+                    Some(v) => v,
+                    None => args
+                        .next()
+                        .ok_or_else(|| format!("--{} needs a value", name))?,
+                };
+                res.values.push((name.to_string(), v));
+            } else {
+                return Err(format!("unknown option --{}", name));
+            }
+        }
+        Ok(res)
+    }
+
+    /// The last value of `--name`.
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<Option<T>, String>
+    where
+        T::Err: Display,
+    {
+        match self.values.iter().rev().find(|(k, _)| k == name) {
+            Some((_, v)) => v
+                .parse()
+                .map(Some)
+                .map_err(|e| format!("invalid value {:?} for --{}: {}", v, name, e)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.values
+            .iter()
+            .filter(move |(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.switches.iter().any(|s| s == name)
+    }
+
+    /// The single positional argument, the model path of most commands.
+    pub fn path(&self) -> Result<PathBuf, String> {
+        match self.positional.as_slice() {
+            [path] => Ok(PathBuf::from(path)),
+            [] => Err("no model given".to_string()),
+            [_, extra, ..] => Err(format!("unexpected argument {}", extra)),
+        }
+    }
+}
+
+fn default_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Loads a model file and picks `--bot`, the best bot by default.
+fn load_bot(args: &Args) -> Result<(PathBuf, Population, usize), String> {
+    let path = args.path()?;
+    let population =
+        load_population(&path).map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+    let bot = args.get("bot")?.or_else(|| population.best()).unwrap_or(0);
+    if bot >= population.bots.len() {
+        return Err(format!(
+            "{} has {} bots, no bot {}",
+            path.display(),
+            population.bots.len(),
+            bot
+        ));
+    }
+    Ok((path, population, bot))
+}
+
+/// Builds the training config: defaults, then `--config`, then the flags.
+pub fn config(args: &Args) -> Result<Config, String> {
+    let mut config = match args.get::<PathBuf>("config")? {
+        Some(path) => Config::read(&path)?,
+        None => Config::default(),
+    };
+    for (flag, key) in [
+        ("output", "path"),
+        ("workers", "workers"),
+        ("depth", "depth"),
+        ("population-size", "population"),
+        ("seed", "seed"),
+        ("metrics", "metrics"),
+    ] {
+        if let Some(v) = args.get::<String>(flag)? {
+            config.set(key, &v)?;
+        }
+    }
+    for setting in args.all("set") {
+        let (key, v) = setting
+            .split_once('=')
+            .ok_or_else(|| format!("--set expects key=value, got {}", setting))?;
+        config.set(key.trim(), v.trim())?;
+    }
+    Ok(config)
+}
+
+pub fn train(args: Vec<String>) -> Result<(), String> {
+    let args = Args::parse(
+        args,
+        &[
+            "config",
+            "output",
+            "workers",
+            "depth",
+            "population-size",
+            "seed",
+            "generations",
+            "metrics",
+            "set",
+            "listen",
+        ],
+        &[],
+    )?;
+    if let Some(extra) = args.positional.first() {
+        return Err(format!("unexpected argument {}", extra));
+    }
+    let config = config(&args)?;
+    let generations: Option<usize> = args.get("generations")?;
+    let workers = config.workers;
+    let mut sink = match &config.metrics {
+        Some(path) => Some(
+            Sink::open(path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?,
+        ),
+        None => None,
+    };
+    let coordinator = match args.get::<String>("listen")? {
+        Some(addr) => {
+            let coordinator = Coordinator::bind(&addr)
+                .map_err(|e| format!("failed to listen on {}: {}", addr, e))?;
+            println!("listening for workers on {}", coordinator.addr);
+            Some(coordinator)
+        }
+        None => None,
+    };
+    shutdown::install()?;
+    let mut world = World::new(config).map_err(|e| format!("failed to load population: {}", e))?;
+    let first = world.generation;
+    let end = generations.map(|n| world.generation + n);
+    let mut last = None;
+    while end.is_none_or(|end| world.generation < end) && !shutdown::requested() {
+        let record = match &coordinator {
+            Some(coordinator) => world.run_distributed(coordinator, workers),
+            None => world.run(workers),
+        };
+        if let Some(sink) = sink.as_mut() {
+            if let Err(e) = sink.write(&record) {
+                eprintln!("failed to write metrics: {}", e);
+            }
+        }
+        last = Some(record);
+        world.update();
+    }
+    // unless `update` has just saved
+    if world.generation % world.config.save_interval.max(1) != 0 {
+        world
+            .save()
+            .map_err(|e| format!("failed to save {}: {}", world.config.path.display(), e))?;
+    }
+    println!(
+        "{} after {} generations in {:.1}s, saved generation {} to {}",
+        if shutdown::requested() {
+            "stopped"
+        } else {
+            "finished"
+        },
+        world.generation - first,
+        world.started.elapsed().as_secs_f64(),
+        world.generation,
+        world.config.path.display()
+    );
+    if let Some(record) = last {
+        println!(
+            "last generation: best {}, mean {}, worst {}, {:.0} moves/s",
+            record.best,
+            record.mean,
+            record.worst,
+            record.moves_per_sec()
+        );
+    }
+    if let Some(best) = world.hall.best() {
+        println!(
+            "hall of fame best: {} (generation {})",
+            best.fitness, best.generation
+        );
+    }
+    Ok(())
+}
+
+pub fn worker(args: Vec<String>) -> Result<(), String> {
+    let args = Args::parse(args, &["workers"], &[])?;
+    let addr = match args.positional.as_slice() {
+        [addr] => addr,
+        [] => return Err("no coordinator address given".to_string()),
+        [_, extra, ..] => return Err(format!("unexpected argument {}", extra)),
+    };
+    let workers = args.get("workers")?.unwrap_or_else(default_workers);
+    println!("connecting to {} with {} threads", addr, workers);
+    run_worker(addr.as_str(), workers).map_err(|e| format!("worker failed: {}", e))?;
+    println!("coordinator closed the connection");
+    Ok(())
+}
+
+pub fn eval(args: Vec<String>) -> Result<(), String> {
+    let args = Args::parse(args, &["seeds", "depth", "agent", "bot", "workers"], &[])?;
+    let seeds = parse_seeds(&args.get::<String>("seeds")?.unwrap_or("1..101".to_string()))?;
+    let agent = Agent::parse(
+        &args.get::<String>("agent")?.unwrap_or("search".to_string()),
+        args.get("depth")?.unwrap_or(4),
+    )?;
+    let workers = args.get("workers")?.unwrap_or_else(default_workers);
+    let (path, population, bot) = load_bot(&args)?;
+    let (network, fitness) = &population.bots[bot];
+
+    println!(
+        "{} bot {} (generation {}, fitness {}), {}, {} seeds, {} workers",
+        path.display(),
+        bot,
+        population.generation,
+        fitness,
+        agent,
+        seeds.len(),
+        workers
+    );
+    println!("{}", benchmark(network, agent, &seeds, workers));
+    Ok(())
+}
+
+pub fn play(args: Vec<String>) -> Result<(), String> {
+    let args = Args::parse(
+        args,
+        &["seed", "depth", "bot", "board"],
+        &["color", "unicode"],
+    )?;
+    let seed = args.get("seed")?.unwrap_or_else(crate::engine::random_seed);
+    let depth = args.get("depth")?.unwrap_or(4);
+    if depth == 0 {
+        return Err("depth must be at least 1".to_string());
+    }
+    let style = Style {
+        color: args.has("color"),
+        unicode: args.has("unicode"),
+    };
+    let (_, population, bot) = load_bot(&args)?;
+    let game = Game::new(population.bots[bot].0);
+
+    println!("seed {}", seed);
+    let mut board = match args.get::<Data>("board")? {
+        Some(data) => Board::from_data(seed, data),
+        None => Board::new(seed),
+    };
+    let (mut moves, mut points) = (0, 0);
+    println!("board {}", board.data.notation());
+    print!("{}", render(&board.data, style));
+    while let Some((next, p)) = game.ai(depth, board) {
+        board = next;
+        moves += 1;
+        points += p as usize;
+        println!(
+            "move {}, score {}, board {}",
+            moves,
+            points,
+            board.data.notation()
+        );
+        print!("{}", render(&board.data, style));
+    }
+    println!(
+        "{} moves, score {}, max tile {}",
+        moves,
+        points,
+        1u32 << board.data.max_tile()
+    );
+    Ok(())
+}
+
+pub fn export(args: Vec<String>) -> Result<(), String> {
+    let args = Args::parse(args, &["output", "bot"], &["all"])?;
+    let output: PathBuf = args
+        .get("output")?
+        .ok_or_else(|| "--output is required".to_string())?;
+    let (path, mut population, bot) = load_bot(&args)?;
+    let what = if args.has("all") {
+        format!("{} bots", population.bots.len())
+    } else {
+        population.bots = vec![population.bots.swap_remove(bot)];
+        format!("bot {}", bot)
+    };
+    let data = if output.extension().is_some_and(|e| e == "npz") {
+        npz::export_population(&population)
+    } else {
+        population.encode()
+    };
+    std::fs::write(&output, data)
+        .map_err(|e| format!("failed to write {}: {}", output.display(), e))?;
+    println!(
+        "exported {} of {} to {}",
+        what,
+        path.display(),
+        output.display()
+    );
+    Ok(())
+}
+
+pub fn pack(args: Vec<String>) -> Result<(), String> {
+    let args = Args::parse(args, &["output", "bot"], &[])?;
+    let (path, population, bot) = load_bot(&args)?;
+    let origin = format!(
+        "{} bot {}, generation {}",
+        path.display(),
+        bot,
+        population.generation
+    );
+    // the search the bot was trained with, zero for populations saved before
+    // it was recorded
+    let points_weight = match population
+        .metadata
+        .iter()
+        .find(|(k, _)| k == "points_weight")
+    {
+        Some((_, v)) => v
+            .parse::<f32>()
+            .ok()
+            .filter(|w| w.is_finite())
+            .ok_or_else(|| format!("invalid points_weight in {}: {}", path.display(), v))?,
+        None => 0.0,
+    };
+    let source = pack::weights_source(&population.bots[bot].0, points_weight, Some(&origin));
+    match args.get::<PathBuf>("output")? {
+        Some(output) => {
+            std::fs::write(&output, source)
+                .map_err(|e| format!("failed to write {}: {}", output.display(), e))?;
+            println!("packed {} into {}", origin, output.display());
+        }
+        None => print!("{}", source),
+    }
+    Ok(())
+}
+
+pub fn inspect(args: Vec<String>) -> Result<(), String> {
+    let args = Args::parse(args, &[], &[])?;
+    let path = args.path()?;
+    let population =
+        load_population(&path).map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+    println!("{}", path.display());
+    println!("generation  {}", population.generation);
+    println!("bots        {}", population.bots.len());
+    if let Some(best) = population.best() {
+        println!("best        bot {} ({})", best, population.bots[best].1);
+    }
+    println!(
+        "fitness     {}",
+        Summary::new(population.bots.iter().map(|(_, f)| *f))
+    );
+    println!("topology    {} weights", Network::size());
+    for layer in Network::topology() {
+        println!(
+            "  {:<12} {:?} {} -> {}",
+            layer.name, layer.kind, layer.inputs, layer.outputs
+        );
+    }
+    for (key, value) in population.metadata.iter() {
+        println!("{:<11} {}", key, value);
+    }
+    for (tag, data) in population.extra.0.iter() {
+        println!(
+            "section     {} ({} bytes)",
+            String::from_utf8_lossy(tag),
+            data.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let parsed = Args::parse(
+            args("a.bin --depth 3 --bot=2 --all"),
+            &["depth", "bot"],
+            &["all"],
+        )
+        .unwrap();
+        assert_eq!(parsed.path().unwrap(), PathBuf::from("a.bin"));
+        assert_eq!(parsed.get::<usize>("depth").unwrap(), Some(3));
+        assert_eq!(parsed.get::<usize>("bot").unwrap(), Some(2));
+        assert_eq!(parsed.get::<usize>("seed").unwrap(), None);
+        assert!(parsed.has("all"));
+
+        assert!(Args::parse(args("--depth"), &["depth"], &[]).is_err());
+        assert!(Args::parse(args("--colour red"), &["depth"], &[]).is_err());
+        let parsed = Args::parse(args("--depth x"), &["depth"], &[]).unwrap();
+        assert!(parsed.get::<usize>("depth").is_err());
+    }
+
+    #[test]
+    fn config_flags() {
+        let parsed = Args::parse(
+            args("--population-size 10 --seed 5 --set selection=rank --set depth=2"),
+            &["population-size", "seed", "set"],
+            &[],
+        )
+        .unwrap();
+        let config = config(&parsed).unwrap();
+        assert_eq!(config.population(), 10);
+        assert_eq!(config.seed, Some(5));
+        assert_eq!(config.depth, 2);
+        assert_eq!(config.selection.to_string(), "rank");
+    }
+
+    #[test]
+    fn train_stop() {
+        let dir = std::env::temp_dir().join(format!("2048ai-cli-{}", std::process::id()));
+        let path = dir.join("population.bin");
+        let run = || {
+            train(args(&format!(
+                "--output {} --population-size 4 --depth 1 --seed 3 --generations 2 \
+                 --set hall_size=0 --set save_interval=5",
+                path.display()
+            )))
+        };
+        run().unwrap();
+        assert_eq!(Population::read(&path).unwrap().generation, 2);
+        // after a signal no further generation starts, the state is saved
+        shutdown::request();
+        run().unwrap();
+        assert_eq!(Population::read(&path).unwrap().generation, 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
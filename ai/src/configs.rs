@@ -1,3 +1,16 @@
+//! Training settings. The constants are the defaults; every field of
+//! `Config` can be overridden from a config file of `key = value` lines or
+//! from the command line, see `Config::set` for the keys.
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::curriculum::Schedule;
+use crate::diversity::Niching;
+use crate::eval::{parse_seeds, Aggregate, Fitness, Objective};
+use crate::genetic::{Crossover, Mutation, Selection};
+
 pub const MUTATION_RATE: f32 = 0.01;
 pub const MUTATION_RANGE: f32 = 0.2;
 
@@ -6,4 +19,325 @@ pub const DO_MUTATION: usize = 3000;
 pub const DO_CHANGE: usize = 500;
 pub const NUM_BOTS: usize = DO_CHANGE + DO_CROSS * 2 + DO_MUTATION;
 
+/// Best bots copied unchanged into the next generation, taken out of
+/// `DO_CHANGE`.
+pub const ELITISM: usize = 0;
+pub const SELECTION: Selection = Selection::Roulette;
+pub const MUTATION: Mutation = Mutation::Uniform {
+    rate: MUTATION_RATE,
+    range: MUTATION_RANGE,
+};
+pub const CROSSOVER: Crossover = Crossover::Uniform;
+
+/// Seeds every bot plays per generation, shared by the whole population.
+pub const SEEDS_PER_GENERATION: usize = 1;
+pub const AGGREGATE: Aggregate = Aggregate::Mean;
+/// What a game is worth, see `eval::Fitness`.
+pub const FITNESS: Fitness = Fitness::Moves;
+/// Weight of the merge points in the search, see `Game::points_weight`.
+pub const POINTS_WEIGHT: f32 = 0.0;
+
+/// Networks kept in the hall of fame, zero to disable it.
+pub const HALL_SIZE: usize = 10;
+/// Best bots of each generation tried for the hall of fame.
+pub const HALL_CANDIDATES: usize = 1;
+pub const VALIDATION_SEEDS: &str = "1000000..1000032";
+
 pub const SAVE_INTERVAL: usize = 1;
+pub const PATH: &str = "population.bin";
+/// Population files kept on disk: the latest plus rotated older ones.
+pub const CHECKPOINTS: usize = 3;
+pub const WORKERS: usize = 8;
+pub const DEPTH: usize = 4;
+
+/// Niching of the parent selection, see `diversity::Niching`.
+pub const NICHING: Niching = Niching::None;
+/// Weight of novelty against fitness in the parent selection, 0 to disable
+/// novelty search.
+pub const NOVELTY: f64 = 0.0;
+pub const NOVELTY_NEIGHBOURS: usize = 15;
+/// Behaviours kept in the novelty archive, oldest dropped first.
+pub const NOVELTY_ARCHIVE: usize = 500;
+
+/// Share of the games started from archived boards, see `curriculum`.
+pub const CURRICULUM: Schedule = Schedule::Off;
+pub const CURRICULUM_POOL: usize = 1000;
+/// Smallest max tile (as an exponent, 9 for 512) of an archived board.
+pub const CURRICULUM_MIN_TILE: u32 = 9;
+
+pub const OPTIMIZER: Optimizer = Optimizer::Genetic;
+pub const CMA_POPULATION: usize = 64;
+pub const CMA_SIGMA: f64 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Optimizer {
+    /// Mutation, crossover and copy with the operators of `genetic`, see
+    /// `World::update`.
+    Genetic,
+    /// Separable CMA-ES, see `cmaes::SepCmaEs`.
+    CmaEs,
+}
+
+impl Optimizer {
+    pub fn name(self) -> &'static str {
+        match self {
+            Optimizer::Genetic => "genetic",
+            Optimizer::CmaEs => "cma-es",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "genetic" | "ga" => Some(Optimizer::Genetic),
+            "cma-es" | "cmaes" => Some(Optimizer::CmaEs),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub optimizer: Optimizer,
+    /// Candidates per generation (lambda) when running CMA-ES.
+    pub cma_population: usize,
+    /// Initial CMA-ES step size.
+    pub cma_sigma: f64,
+    pub do_mutation: usize,
+    pub do_cross: usize,
+    pub do_change: usize,
+    pub elitism: usize,
+    pub selection: Selection,
+    pub mutation: Mutation,
+    pub crossover: Crossover,
+    pub niching: Niching,
+    pub novelty: f64,
+    pub novelty_neighbours: usize,
+    pub novelty_archive: usize,
+    pub curriculum: Schedule,
+    pub curriculum_pool: usize,
+    pub curriculum_min_tile: u32,
+    pub seeds_per_generation: usize,
+    pub aggregate: Aggregate,
+    pub fitness: Fitness,
+    pub points_weight: f32,
+    pub hall_size: usize,
+    pub hall_candidates: usize,
+    /// Fixed seeds the hall of fame is ranked on.
+    pub validation_seeds: Vec<u64>,
+    /// Population file, loaded on start and rewritten every
+    /// `save_interval` generations.
+    pub path: PathBuf,
+    pub save_interval: usize,
+    /// Per-generation metrics, CSV for a `.csv` path, JSON Lines otherwise.
+    pub metrics: Option<PathBuf>,
+    pub checkpoints: usize,
+    pub workers: usize,
+    /// Search depth of `Game::run_with_ai` during training.
+    pub depth: usize,
+    /// Seeds the training RNG; random when unset.
+    pub seed: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            optimizer: OPTIMIZER,
+            cma_population: CMA_POPULATION,
+            cma_sigma: CMA_SIGMA,
+            do_mutation: DO_MUTATION,
+            do_cross: DO_CROSS,
+            do_change: DO_CHANGE,
+            elitism: ELITISM,
+            selection: SELECTION,
+            mutation: MUTATION,
+            crossover: CROSSOVER,
+            niching: NICHING,
+            novelty: NOVELTY,
+            novelty_neighbours: NOVELTY_NEIGHBOURS,
+            novelty_archive: NOVELTY_ARCHIVE,
+            curriculum: CURRICULUM,
+            curriculum_pool: CURRICULUM_POOL,
+            curriculum_min_tile: CURRICULUM_MIN_TILE,
+            seeds_per_generation: SEEDS_PER_GENERATION,
+            aggregate: AGGREGATE,
+            fitness: FITNESS,
+            points_weight: POINTS_WEIGHT,
+            hall_size: HALL_SIZE,
+            hall_candidates: HALL_CANDIDATES,
+            validation_seeds: parse_seeds(VALIDATION_SEEDS).unwrap(),
+            path: PathBuf::from(PATH),
+            save_interval: SAVE_INTERVAL,
+            checkpoints: CHECKPOINTS,
+            metrics: None,
+            workers: WORKERS,
+            depth: DEPTH,
+            seed: None,
+        }
+    }
+}
+
+fn value<T: FromStr>(key: &str, value: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("invalid value {:?} for {}: {}", value, key, e))
+}
+
+impl Config {
+    pub fn population(&self) -> usize {
+        match self.optimizer {
+            Optimizer::Genetic => self.do_mutation + self.do_cross * 2 + self.do_change,
+            Optimizer::CmaEs => self.cma_population,
+        }
+    }
+
+    pub fn objective(&self) -> Objective {
+        Objective {
+            depth: self.depth,
+            aggregate: self.aggregate,
+            fitness: self.fitness,
+            points_weight: self.points_weight,
+        }
+    }
+
+    /// Resizes both optimisers. The genetic algorithm keeps the ratio of
+    /// mutated, crossed and copied bots.
+    pub fn set_population(&mut self, size: usize) -> Result<(), String> {
+        if size == 0 {
+            return Err("population must be at least 1".to_string());
+        }
+        let total = (self.do_mutation + self.do_cross * 2 + self.do_change).max(1);
+        self.do_mutation = size * self.do_mutation / total;
+        self.do_cross = size * self.do_cross / total;
+        self.do_change = size - self.do_mutation - self.do_cross * 2;
+        self.cma_population = size;
+        Ok(())
+    }
+
+    /// Sets one setting by the name of its field. `population` resizes the
+    /// population, see `set_population`.
+    pub fn set(&mut self, key: &str, v: &str) -> Result<(), String> {
+        match key {
+            "optimizer" => {
+                self.optimizer = Optimizer::parse(v).ok_or_else(|| {
+                    format!("unknown optimizer {} (expected genetic or cma-es)", v)
+                })?
+            }
+            "cma_population" => self.cma_population = value(key, v)?,
+            "cma_sigma" => self.cma_sigma = value(key, v)?,
+            "do_mutation" => self.do_mutation = value(key, v)?,
+            "do_cross" => self.do_cross = value(key, v)?,
+            "do_change" => self.do_change = value(key, v)?,
+            "population" => self.set_population(value(key, v)?)?,
+            "elitism" => self.elitism = value(key, v)?,
+            "selection" => self.selection = value(key, v)?,
+            "mutation" => self.mutation = value(key, v)?,
+            "crossover" => self.crossover = value(key, v)?,
+            "niching" => self.niching = value(key, v)?,
+            "novelty" => match value(key, v)? {
+                w if (0.0..=1.0).contains(&w) => self.novelty = w,
+                _ => return Err("novelty must be between 0 and 1".to_string()),
+            },
+            "novelty_neighbours" => self.novelty_neighbours = value(key, v)?,
+            "novelty_archive" => self.novelty_archive = value(key, v)?,
+            "curriculum" => self.curriculum = value(key, v)?,
+            "curriculum_pool" => self.curriculum_pool = value(key, v)?,
+            "curriculum_min_tile" => self.curriculum_min_tile = value(key, v)?,
+            "seeds_per_generation" => self.seeds_per_generation = value(key, v)?,
+            "aggregate" => self.aggregate = value(key, v)?,
+            "fitness" => self.fitness = value(key, v)?,
+            "points_weight" => self.points_weight = value(key, v)?,
+            "hall_size" => self.hall_size = value(key, v)?,
+            "hall_candidates" => self.hall_candidates = value(key, v)?,
+            "validation_seeds" => {
+                self.validation_seeds =
+                    parse_seeds(v).map_err(|e| format!("invalid value for {}: {}", key, e))?
+            }
+            "path" => self.path = PathBuf::from(v),
+            "save_interval" => self.save_interval = value(key, v)?,
+            "checkpoints" => self.checkpoints = value(key, v)?,
+            "metrics" => self.metrics = Some(PathBuf::from(v)),
+            "workers" => self.workers = value(key, v)?,
+            "depth" => match value(key, v)? {
+                0 => return Err("depth must be at least 1".to_string()),
+                depth => self.depth = depth,
+            },
+            "seed" => self.seed = Some(value(key, v)?),
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Applies a config file: `key = value` lines, `#` starts a comment.
+    pub fn apply(&mut self, text: &str) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, v) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected key = value", i + 1))?;
+            self.set(key.trim(), v.trim())
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let mut config = Config::default();
+        config
+            .apply(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let mut config = Config::default();
+        config
+            .apply(
+                "# small run\n\
+                 optimizer = cma-es\n\
+                 selection = tournament:4   # best of four\n\
+                 seed = 42\n\
+                 \n\
+                 population = 100\n",
+            )
+            .unwrap();
+        assert_eq!(config.optimizer, Optimizer::CmaEs);
+        assert_eq!(config.selection, Selection::Tournament(4));
+        assert_eq!(config.seed, Some(42));
+        assert_eq!(config.population(), 100);
+        config.set("optimizer", "genetic").unwrap();
+        assert_eq!(config.population(), 100);
+        assert_eq!(config.do_cross, 100 * DO_CROSS / NUM_BOTS);
+
+        assert_eq!(
+            config.apply("depth = 3\nworkers = many").unwrap_err(),
+            "line 2: invalid value \"many\" for workers: invalid digit found in string"
+        );
+        assert!(config.set("colour", "red").is_err());
+        assert!(config.set("depth", "0").is_err());
+        assert!(config.set("population", "0").is_err());
+        assert_eq!(config.population(), 100);
+        assert!(config.set("novelty", "1.5").is_err());
+        config.set("niching", "species:0.05").unwrap();
+        assert_eq!(config.niching, Niching::Species { threshold: 0.05 });
+        config.set("fitness", "score").unwrap();
+        config.set("points_weight", "0.01").unwrap();
+        let objective = config.objective();
+        assert_eq!(objective.fitness, Fitness::Score);
+        assert_eq!(objective.points_weight, 0.01);
+        assert!(config.set("fitness", "tiles").is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Curriculum of starting positions.
+//!
+//! Games from `Board::new` spend most of their moves on easy openings. With a
+//! curriculum the trainer keeps a pool of mid- and late-game boards from the
+//! champion's games and starts a share of each generation's games from them,
+//! the rest fresh. Pool boards keep their seed, so a game from one is as
+//! reproducible as a fresh game.
+
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+
+use crate::engine::{Board, Data};
+use crate::format::{FormatError, Reader, Writer};
+use crate::genetic::args;
+
+/// Boards taken from each champion game.
+pub const POSITIONS_PER_GAME: usize = 8;
+
+/// Share of the games started from the pool, by generation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    /// Every game starts fresh.
+    Off,
+    /// A constant share.
+    Fixed(f64),
+    /// From 0 up to `share` over the first `generations`, then constant.
+    Ramp { share: f64, generations: usize },
+}
+
+impl Schedule {
+    pub fn share(&self, generation: usize) -> f64 {
+        match *self {
+            Schedule::Off => 0.0,
+            Schedule::Fixed(share) => share,
+            Schedule::Ramp { share, generations } => {
+                share * (generation as f64 / generations.max(1) as f64).min(1.0)
+            }
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let schedule = if args::<f64>(s, "off", 0)?.is_some() {
+            Schedule::Off
+        } else if let Some(a) = args::<f64>(s, "fixed", 1)? {
+            Schedule::Fixed(a[0])
+        } else if let Some(a) = args::<f64>(s, "ramp", 2)? {
+            Schedule::Ramp {
+                share: a[0],
+                generations: a[1] as usize,
+            }
+        } else {
+            return Err(format!(
+                "unknown curriculum {} (expected off, fixed:<share> or ramp:<share>:<generations>)",
+                s
+            ));
+        };
+        match schedule {
+            Schedule::Fixed(share) | Schedule::Ramp { share, .. }
+                if !(0.0..=1.0).contains(&share) =>
+            {
+                Err("curriculum share must be between 0 and 1".to_string())
+            }
+            schedule => Ok(schedule),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Off => write!(f, "off"),
+            Schedule::Fixed(share) => write!(f, "fixed:{}", share),
+            Schedule::Ramp { share, generations } => write!(f, "ramp:{}:{}", share, generations),
+        }
+    }
+}
+
+/// Archived starting positions, oldest dropped first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pool {
+    pub capacity: usize,
+    pub boards: Vec<Board>,
+}
+
+impl Pool {
+    pub fn new(capacity: usize) -> Self {
+        Pool {
+            capacity,
+            boards: vec![],
+        }
+    }
+
+    /// Archives up to `POSITIONS_PER_GAME` boards of `game`, spread evenly
+    /// over the boards whose max tile is at least `min_tile` (an exponent).
+    /// Returns how many were added.
+    pub fn add_game(&mut self, game: &[Board], min_tile: u32) -> usize {
+        let late: Vec<&Board> = game
+            .iter()
+            .filter(|b| b.data.max_tile() >= min_tile)
+            .collect();
+        let n = late.len().min(POSITIONS_PER_GAME);
+        for i in 0..n {
+            let board = *late[i * late.len() / n];
+            if !self.boards.contains(&board) {
+                self.boards.push(board);
+            }
+        }
+        let excess = self.boards.len().saturating_sub(self.capacity);
+        self.boards.drain(..excess);
+        n
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Option<Board> {
+        match self.boards.len() {
+            0 => None,
+            n => Some(self.boards[rng.gen_range(0..n)]),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u64(self.boards.len() as u64);
+        for board in self.boards.iter() {
+            w.u64(board.seed);
+            w.u64(board.data.0);
+        }
+        w.0
+    }
+
+    pub fn decode(data: &[u8], capacity: usize) -> Result<Self, FormatError> {
+        let mut r = Reader::new(data);
+        let mut pool = Pool::new(capacity);
+        for _ in 0..r.u64()? {
+            pool.boards.push(Board::from_data(r.u64()?, Data(r.u64()?)));
+        }
+        let excess = pool.boards.len().saturating_sub(capacity);
+        pool.boards.drain(..excess);
+        Ok(pool)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::Game;
+    use crate::nn::Network;
+    use rand::prelude::*;
+
+    #[test]
+    fn schedule() {
+        for s in ["off", "fixed:0.25", "ramp:0.5:100"] {
+            assert_eq!(s.parse::<Schedule>().unwrap().to_string(), s);
+        }
+        assert!("fixed:2".parse::<Schedule>().is_err());
+        assert!("ramp:0.5".parse::<Schedule>().is_err());
+        let ramp: Schedule = "ramp:0.5:100".parse().unwrap();
+        assert_eq!(ramp.share(0), 0.0);
+        assert_eq!(ramp.share(50), 0.25);
+        assert_eq!(ramp.share(500), 0.5);
+    }
+
+    #[test]
+    fn pool() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let game = Game::new(Network::new(&mut rng)).trajectory(1, Board::new(7));
+        let mut pool = Pool::new(10);
+        assert_eq!(pool.sample(&mut rng), None);
+        assert_eq!(pool.add_game(&game, 20), 0);
+        assert_eq!(pool.add_game(&game, 3), POSITIONS_PER_GAME);
+        assert!(pool.boards.iter().all(|b| b.data.max_tile() >= 3));
+        // the same boards are not archived twice
+        pool.add_game(&game, 3);
+        assert_eq!(pool.boards.len(), POSITIONS_PER_GAME);
+        pool.add_game(&game, 2);
+        assert_eq!(pool.boards.len(), 10);
+
+        let loaded = Pool::decode(&pool.encode(), 4).unwrap();
+        assert_eq!(loaded.boards, pool.boards[6..]);
+        assert!(pool.boards.contains(&pool.sample(&mut rng).unwrap()));
+    }
+}
@@ -0,0 +1,605 @@
+//! Distributed evaluation over TCP.
+//!
+//! The coordinator (`ai train --listen <addr>`) splits each generation into
+//! jobs of `JOB_SIZE` bots and hands them to whichever worker
+//! (`ai worker <addr>`) asks next. Workers may connect or disconnect at any
+//! time; the job of a worker that drops, or answers for other bots than it
+//! was given, is put back in the queue. Fitness is computed by
+//! `Game::evaluate` exactly as in a local run, so the results do not depend
+//! on how the jobs were spread.
+//!
+//! Every message is a frame `length u32 | kind u8 | body`, little endian:
+//!
+//! ```text
+//! hello   "2048ai-worker" | protocol u32 | threads u32
+//! job     generation u64 | job u64 | depth u32 | aggregate str
+//!         | fitness str | points_weight f32
+//!         | starts u32 (seed u64 | board u64)...
+//!         | bots u32 (index u64 | weights f32 * Network::size())..
+//! result  generation u64 | job u64 | bots u32 (index u64 | fitness f64
+//!         | moves u64 | score u64 | points u64 | behaviour f32 * BEHAVIOUR)..
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::diversity::{Behaviour, BEHAVIOUR};
+use crate::engine::{Board, Data};
+use crate::eval::{par_map, Objective};
+use crate::format::{FormatError, Reader, Writer};
+use crate::game::Game;
+use crate::nn::Network;
+
+pub const HELLO: &str = "2048ai-worker";
+pub const PROTOCOL: u32 = 4;
+/// Bots per job.
+pub const JOB_SIZE: usize = 16;
+const MAX_FRAME: usize = 1 << 30;
+
+const KIND_HELLO: u8 = 1;
+const KIND_JOB: u8 = 2;
+const KIND_RESULT: u8 = 3;
+
+fn invalid(e: FormatError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+pub fn write_frame(stream: &mut impl Write, kind: u8, body: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(body.len() + 5);
+    frame.extend_from_slice(&(body.len() as u32 + 1).to_le_bytes());
+    frame.push(kind);
+    frame.extend_from_slice(body);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Reads one frame, `None` when the peer closed the connection cleanly.
+pub fn read_frame(stream: &mut impl Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut len = [0u8; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len == 0 || len > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad frame length",
+        ));
+    }
+    let mut frame = vec![0u8; len];
+    stream.read_exact(&mut frame)?;
+    let body = frame.split_off(1);
+    Ok(Some((frame[0], body)))
+}
+
+fn expect(frame: Option<(u8, Vec<u8>)>, kind: u8) -> io::Result<Vec<u8>> {
+    match frame {
+        Some((k, body)) if k == kind => Ok(body),
+        Some((k, _)) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected message kind {}", k),
+        )),
+        None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub generation: u64,
+    pub id: u64,
+    pub objective: Objective,
+    pub starts: Vec<Board>,
+    pub bots: Vec<(usize, Network)>,
+}
+
+impl Job {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u64(self.generation);
+        w.u64(self.id);
+        w.u32(self.objective.depth as u32);
+        w.str(&self.objective.aggregate.to_string());
+        w.str(&self.objective.fitness.to_string());
+        w.f32(self.objective.points_weight);
+        w.u32(self.starts.len() as u32);
+        for board in self.starts.iter() {
+            w.u64(board.seed);
+            w.u64(board.data.0);
+        }
+        w.u32(self.bots.len() as u32);
+        for (index, network) in self.bots.iter() {
+            w.u64(*index as u64);
+            w.f32s(&network.dump());
+        }
+        w.0
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        let mut r = Reader::new(data);
+        let generation = r.u64()?;
+        let id = r.u64()?;
+        let depth = r.u32()? as usize;
+        let aggregate = r
+            .str()?
+            .parse()
+            .map_err(|_| FormatError::Invalid("unknown aggregate"))?;
+        let fitness = r
+            .str()?
+            .parse()
+            .map_err(|_| FormatError::Invalid("unknown fitness"))?;
+        let objective = Objective {
+            depth,
+            aggregate,
+            fitness,
+            points_weight: r.f32()?,
+        };
+        let mut starts = Vec::new();
+        for _ in 0..r.u32()? {
+            starts.push(Board::from_data(r.u64()?, Data(r.u64()?)));
+        }
+        let mut bots = Vec::new();
+        for _ in 0..r.u32()? {
+            let index = r.u64()? as usize;
+            bots.push((index, Network::load(&r.f32s(Network::size())?)));
+        }
+        Ok(Job {
+            generation,
+            id,
+            objective,
+            starts,
+            bots,
+        })
+    }
+
+    /// Plays the job on `threads` threads.
+    pub fn run(&self, threads: usize) -> JobResult {
+        let bots = par_map(&self.bots, threads, |(index, network)| {
+            let mut game = Game::new(*network);
+            game.evaluate_from(&self.starts, self.objective);
+            (*index, game)
+        });
+        JobResult {
+            generation: self.generation,
+            id: self.id,
+            bots: bots
+                .into_iter()
+                .map(|(index, g)| (index, Scored::new(&g)))
+                .collect(),
+        }
+    }
+}
+
+/// What a worker reports for one bot, the fields `Game::evaluate` sets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scored {
+    pub fitness: f64,
+    pub moves: usize,
+    pub score: usize,
+    pub points: usize,
+    pub behaviour: Behaviour,
+}
+
+impl Scored {
+    pub fn new(game: &Game) -> Self {
+        Scored {
+            fitness: game.fitness,
+            moves: game.moves,
+            score: game.score,
+            points: game.points,
+            behaviour: game.behaviour,
+        }
+    }
+
+    pub fn apply(&self, game: &Game) -> Game {
+        Game {
+            fitness: self.fitness,
+            moves: self.moves,
+            score: self.score,
+            points: self.points,
+            behaviour: self.behaviour,
+            ..*game
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobResult {
+    pub generation: u64,
+    pub id: u64,
+    /// Bot index in the generation and its result.
+    pub bots: Vec<(usize, Scored)>,
+}
+
+impl JobResult {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u64(self.generation);
+        w.u64(self.id);
+        w.u32(self.bots.len() as u32);
+        for (index, scored) in self.bots.iter() {
+            w.u64(*index as u64);
+            w.f64(scored.fitness);
+            w.u64(scored.moves as u64);
+            w.u64(scored.score as u64);
+            w.u64(scored.points as u64);
+            w.f32s(&scored.behaviour);
+        }
+        w.0
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        let mut r = Reader::new(data);
+        let generation = r.u64()?;
+        let id = r.u64()?;
+        let mut bots = Vec::new();
+        for _ in 0..r.u32()? {
+            let index = r.u64()? as usize;
+            let scored = Scored {
+                fitness: r.f64()?,
+                moves: r.u64()? as usize,
+                score: r.u64()? as usize,
+                points: r.u64()? as usize,
+                behaviour: r.f32s(BEHAVIOUR)?.try_into().unwrap(),
+            };
+            bots.push((index, scored));
+        }
+        Ok(JobResult {
+            generation,
+            id,
+            bots,
+        })
+    }
+
+    /// Whether the result holds each bot of `indices` once and no other.
+    fn covers(&self, indices: &[usize]) -> bool {
+        let mut bots: Vec<usize> = self.bots.iter().map(|(index, _)| *index).collect();
+        let mut indices = indices.to_vec();
+        bots.sort_unstable();
+        indices.sort_unstable();
+        bots == indices
+    }
+}
+
+/// The generation being evaluated.
+#[derive(Default)]
+struct State {
+    generation: u64,
+    objective: Option<Objective>,
+    starts: Vec<Board>,
+    networks: Vec<Network>,
+    next_job: u64,
+    /// Jobs waiting for a worker, as bot indices.
+    queue: VecDeque<(u64, Vec<usize>)>,
+    results: Vec<Option<Scored>>,
+    remaining: usize,
+    workers: usize,
+}
+
+impl State {
+    fn job(&self, id: u64, indices: &[usize]) -> Job {
+        Job {
+            generation: self.generation,
+            id,
+            objective: self.objective.unwrap(),
+            starts: self.starts.clone(),
+            bots: indices.iter().map(|i| (*i, self.networks[*i])).collect(),
+        }
+    }
+}
+
+type Shared = Arc<(Mutex<State>, Condvar)>;
+
+pub struct Coordinator {
+    pub addr: SocketAddr,
+    shared: Shared,
+}
+
+impl Coordinator {
+    /// Listens on `addr` and serves every worker that connects.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shared: Shared = Arc::default();
+        let accept = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = accept.clone();
+                thread::spawn(move || {
+                    let peer = stream.peer_addr().ok();
+                    if let Err(e) = serve(stream, &shared) {
+                        eprintln!("worker {:?} disconnected: {}", peer, e);
+                    }
+                });
+            }
+        });
+        Ok(Coordinator { addr, shared })
+    }
+
+    pub fn workers(&self) -> usize {
+        self.shared.0.lock().unwrap().workers
+    }
+
+    /// Evaluates `bots` on the connected workers, waiting for workers if
+    /// there are none. Returns the bots with `Game::evaluate` applied.
+    pub fn evaluate(&self, bots: &[Game], starts: &[Board], objective: Objective) -> Vec<Game> {
+        let (lock, cvar) = &*self.shared;
+        let mut state = lock.lock().unwrap();
+        state.generation += 1;
+        state.objective = Some(objective);
+        state.starts = starts.to_vec();
+        state.networks = bots.iter().map(|b| b.network).collect();
+        state.results = vec![None; bots.len()];
+        state.remaining = bots.len();
+        let indices: Vec<usize> = (0..bots.len()).collect();
+        state.queue.clear();
+        for chunk in indices.chunks(JOB_SIZE) {
+            let id = state.next_job;
+            state.next_job += 1;
+            state.queue.push_back((id, chunk.to_vec()));
+        }
+        cvar.notify_all();
+
+        let mut waiting = false;
+        while state.remaining > 0 {
+            if state.workers == 0 && !waiting {
+                println!("waiting for workers on {}", self.addr);
+            }
+            waiting = state.workers == 0;
+            state = cvar.wait(state).unwrap();
+        }
+        bots.iter()
+            .zip(state.results.iter())
+            .map(|(bot, result)| Game {
+                points_weight: objective.points_weight,
+                ..result.unwrap().apply(bot)
+            })
+            .collect()
+    }
+}
+
+/// Runs one worker connection until it closes.
+fn serve(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let hello = expect(read_frame(&mut stream)?, KIND_HELLO)?;
+    let mut r = Reader::new(&hello);
+    let (name, protocol, threads) = (
+        r.str().map_err(invalid)?,
+        r.u32().map_err(invalid)?,
+        r.u32().map_err(invalid)?,
+    );
+    if name != HELLO || protocol != PROTOCOL {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported worker {} protocol {}", name, protocol),
+        ));
+    }
+    let (lock, cvar) = &**shared;
+    let peer = stream.peer_addr()?;
+    {
+        let mut state = lock.lock().unwrap();
+        state.workers += 1;
+        println!(
+            "worker {} joined with {} threads ({} connected)",
+            peer, threads, state.workers
+        );
+        cvar.notify_all();
+    }
+    let result = work(&mut stream, shared);
+    let mut state = lock.lock().unwrap();
+    state.workers -= 1;
+    println!("worker {} left ({} connected)", peer, state.workers);
+    cvar.notify_all();
+    result
+}
+
+fn work(stream: &mut TcpStream, shared: &Shared) -> io::Result<()> {
+    let (lock, cvar) = &**shared;
+    loop {
+        let (generation, id, indices, job) = {
+            let mut state = cvar
+                .wait_while(lock.lock().unwrap(), |s| s.queue.is_empty())
+                .unwrap();
+            let (id, indices) = state.queue.pop_front().unwrap();
+            let job = state.job(id, &indices);
+            (state.generation, id, indices, job)
+        };
+        let reply = write_frame(stream, KIND_JOB, &job.encode())
+            .and_then(|_| expect(read_frame(stream)?, KIND_RESULT))
+            .and_then(|body| JobResult::decode(&body).map_err(invalid));
+        let mut state = lock.lock().unwrap();
+        match reply {
+            Ok(result)
+                if result.generation == generation
+                    && result.id == id
+                    && result.covers(&indices) =>
+            {
+                if state.generation == generation {
+                    for (index, scored) in result.bots {
+                        if state.results[index].replace(scored).is_none() {
+                            state.remaining -= 1;
+                        }
+                    }
+                    cvar.notify_all();
+                }
+            }
+            other => {
+                // hand the job to another worker
+                if state.generation == generation {
+                    state.queue.push_front((id, indices));
+                    cvar.notify_all();
+                }
+                return match other {
+                    Err(e) => Err(e),
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "mismatched result",
+                    )),
+                };
+            }
+        }
+    }
+}
+
+/// Connects to a coordinator and evaluates jobs on `threads` threads until
+/// the coordinator goes away.
+pub fn run_worker(addr: impl ToSocketAddrs, threads: usize) -> io::Result<()> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    let mut w = Writer::new();
+    w.str(HELLO);
+    w.u32(PROTOCOL);
+    w.u32(threads as u32);
+    write_frame(&mut stream, KIND_HELLO, &w.0)?;
+    while let Some(frame) = read_frame(&mut stream)? {
+        let job = Job::decode(&expect(Some(frame), KIND_JOB)?).map_err(invalid)?;
+        let result = job.run(threads);
+        write_frame(&mut stream, KIND_RESULT, &result.encode())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::{Aggregate, Fitness};
+    use rand::prelude::*;
+
+    const OBJECTIVE: Objective = Objective {
+        depth: 1,
+        aggregate: Aggregate::Mean,
+        fitness: Fitness::Moves,
+        points_weight: 0.0,
+    };
+
+    fn bots(n: usize) -> Vec<Game> {
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..n).map(|_| Game::new(Network::new(&mut rng))).collect()
+    }
+
+    fn local(bots: &[Game], starts: &[Board]) -> Vec<Scored> {
+        bots.iter()
+            .map(|b| {
+                let mut b = *b;
+                b.evaluate_from(starts, OBJECTIVE);
+                Scored::new(&b)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn messages() {
+        let job = Job {
+            generation: 2,
+            id: 7,
+            objective: Objective {
+                depth: 3,
+                aggregate: Aggregate::Quantile(0.25),
+                fitness: Fitness::Score,
+                points_weight: 0.5,
+            },
+            starts: vec![Board::new(1), Board::new(2)],
+            bots: bots(2)
+                .iter()
+                .enumerate()
+                .map(|(i, b)| (i, b.network))
+                .collect(),
+        };
+        let decoded = Job::decode(&job.encode()).unwrap();
+        assert_eq!(decoded.objective, job.objective);
+        assert_eq!(decoded.starts, job.starts);
+        assert_eq!(decoded.bots[1].1.dump(), job.bots[1].1.dump());
+        let result = JobResult {
+            generation: 2,
+            id: 7,
+            bots: vec![(0, Scored::new(&bots(1)[0]))],
+        };
+        assert_eq!(JobResult::decode(&result.encode()).unwrap(), result);
+        assert!(Job::decode(&job.encode()[..20]).is_err());
+    }
+
+    /// A connection that has said hello, for workers written by hand.
+    fn connect(addr: SocketAddr) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut w = Writer::new();
+        w.str(HELLO);
+        w.u32(PROTOCOL);
+        w.u32(1);
+        write_frame(&mut stream, KIND_HELLO, &w.0).unwrap();
+        stream
+    }
+
+    #[test]
+    fn localhost() {
+        let coordinator = Coordinator::bind("127.0.0.1:0").unwrap();
+        let addr = coordinator.addr;
+        let population = bots(40);
+        let starts = [Board::new(5), Board::new(6)];
+
+        // a worker that takes a job and vanishes without answering
+        let mut quitter = connect(addr);
+        let joined = thread::spawn(move || {
+            let job = expect(read_frame(&mut quitter).unwrap(), KIND_JOB).unwrap();
+            assert!(!Job::decode(&job).unwrap().bots.is_empty());
+            drop(quitter);
+            // the real workers join only after the quitter left
+            for threads in [1, 2] {
+                thread::spawn(move || run_worker(addr, threads).unwrap());
+            }
+        });
+
+        let evaluated = coordinator.evaluate(&population, &starts, OBJECTIVE);
+        joined.join().unwrap();
+        let remote: Vec<Scored> = evaluated.iter().map(Scored::new).collect();
+        assert_eq!(remote, local(&population, &starts));
+
+        // a second generation on the same workers, from a mid-game board
+        let start = [Game::new(population[0].network).trajectory(1, Board::new(9))[30]];
+        let evaluated = coordinator.evaluate(&population[..3], &start, OBJECTIVE);
+        let remote: Vec<Scored> = evaluated.iter().map(Scored::new).collect();
+        assert_eq!(remote, local(&population[..3], &start));
+    }
+
+    #[test]
+    fn wrong_bots() {
+        let coordinator = Coordinator::bind("127.0.0.1:0").unwrap();
+        let addr = coordinator.addr;
+        let population = bots(3);
+        let starts = [Board::new(7)];
+
+        // workers answering for a bot out of range, for too few bots and for
+        // the first bot in place of all; each is dropped and its job handed on
+        let joined = thread::spawn(move || {
+            for liar in 0..3 {
+                let mut stream = connect(addr);
+                let job = expect(read_frame(&mut stream).unwrap(), KIND_JOB).unwrap();
+                let job = Job::decode(&job).unwrap();
+                let first = job.bots[0].0;
+                let indices = match liar {
+                    0 => vec![1000],
+                    1 => vec![first],
+                    _ => vec![first; job.bots.len()],
+                };
+                let scored = Scored::new(&Game::new(job.bots[0].1));
+                let result = JobResult {
+                    generation: job.generation,
+                    id: job.id,
+                    bots: indices.into_iter().map(|i| (i, scored)).collect(),
+                };
+                write_frame(&mut stream, KIND_RESULT, &result.encode()).unwrap();
+                // the coordinator hangs up
+                assert!(!matches!(read_frame(&mut stream), Ok(Some(_))));
+            }
+            thread::spawn(move || run_worker(addr, 1).unwrap());
+        });
+
+        let evaluated = coordinator.evaluate(&population, &starts, OBJECTIVE);
+        joined.join().unwrap();
+        let remote: Vec<Scored> = evaluated.iter().map(Scored::new).collect();
+        assert_eq!(remote, local(&population, &starts));
+    }
+}
@@ -0,0 +1,260 @@
+//! Keeping the population from collapsing onto one lineage: genome distance,
+//! fitness sharing, NEAT-style species and novelty search.
+//!
+//! Genomes are compared on the flat parameter vector of `Network::dump`,
+//! behaviours on the `Behaviour` descriptor `Game::evaluate` records. Both
+//! only change the scores the parents are selected on; `Game::fitness`, the
+//! ranking, elitism and the hall of fame keep the raw fitness.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::eval::par_map;
+use crate::genetic::args;
+
+/// Length of `Behaviour`.
+pub const BEHAVIOUR: usize = 20;
+
+/// How a bot plays, averaged over its seeds: the first 16 values are the
+/// share of games ending with the max tile in each cell (row by row), the
+/// last 4 the share of moves going up, down, left and right.
+pub type Behaviour = [f32; BEHAVIOUR];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Niching {
+    /// Select on the raw fitness.
+    None,
+    /// Divides the fitness by the niche count `sum_j max(0, 1 - d_ij / radius)`
+    /// over the whole population, O(n^2) distances per generation.
+    Sharing { radius: f64 },
+    /// Groups bots into species, each joining the first (fittest) species
+    /// whose leader is within `threshold`, and divides the fitness by the
+    /// species size as in NEAT.
+    Species { threshold: f64 },
+}
+
+impl FromStr for Niching {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if args::<f64>(s, "none", 0)?.is_some() {
+            Ok(Niching::None)
+        } else if let Some(a) = args::<f64>(s, "sharing", 1)? {
+            Ok(Niching::Sharing { radius: a[0] })
+        } else if let Some(a) = args::<f64>(s, "species", 1)? {
+            Ok(Niching::Species { threshold: a[0] })
+        } else {
+            Err(format!(
+                "unknown niching {} (expected none, sharing:<radius> or species:<threshold>)",
+                s
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Niching {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Niching::None => write!(f, "none"),
+            Niching::Sharing { radius } => write!(f, "sharing:{}", radius),
+            Niching::Species { threshold } => write!(f, "species:{}", threshold),
+        }
+    }
+}
+
+/// Root mean square difference of two genomes: about 0.82 between random
+/// networks, about 0.01 after one default mutation.
+pub fn distance(a: &[f32], b: &[f32]) -> f64 {
+    let sum: f64 = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| (*x as f64 - *y as f64).powi(2))
+        .sum();
+    (sum / a.len().max(1) as f64).sqrt()
+}
+
+/// Mean distance between the pairs of up to `sample` bots spread evenly
+/// over `genomes`.
+pub fn mean_distance(genomes: &[Vec<f32>], sample: usize) -> f64 {
+    let n = genomes.len().min(sample);
+    if n < 2 {
+        return 0.0;
+    }
+    let picked: Vec<&Vec<f32>> = (0..n).map(|i| &genomes[i * genomes.len() / n]).collect();
+    let mut sum = 0.0;
+    for i in 0..n {
+        for j in i + 1..n {
+            sum += distance(picked[i], picked[j]);
+        }
+    }
+    sum / (n * (n - 1) / 2) as f64
+}
+
+/// Species of every genome, numbered in order of their leaders. Genomes are
+/// expected best first, so every species is led by its fittest member.
+pub fn species(genomes: &[Vec<f32>], threshold: f64) -> Vec<usize> {
+    let mut leaders: Vec<usize> = vec![];
+    genomes
+        .iter()
+        .enumerate()
+        .map(|(i, genome)| {
+            leaders
+                .iter()
+                .position(|l| distance(&genomes[*l], genome) < threshold)
+                .unwrap_or_else(|| {
+                    leaders.push(i);
+                    leaders.len() - 1
+                })
+        })
+        .collect()
+}
+
+/// Applies `niching` to `fitness`. Also returns the number of species, zero
+/// unless `niching` is `Species`.
+pub fn niche(
+    niching: Niching,
+    fitness: &[f64],
+    genomes: &[Vec<f32>],
+    workers: usize,
+) -> (Vec<f64>, usize) {
+    match niching {
+        Niching::None => (fitness.to_vec(), 0),
+        Niching::Sharing { radius } => {
+            let indices: Vec<usize> = (0..genomes.len()).collect();
+            let counts = par_map(&indices, workers, |i| {
+                genomes
+                    .iter()
+                    .map(|g| (1.0 - distance(&genomes[*i], g) / radius).max(0.0))
+                    .sum::<f64>()
+            });
+            let shared = fitness.iter().zip(counts).map(|(f, c)| f / c).collect();
+            (shared, 0)
+        }
+        Niching::Species { threshold } => {
+            let species = species(genomes, threshold);
+            let count = species.iter().max().map_or(0, |s| s + 1);
+            let mut sizes = vec![0usize; count];
+            species.iter().for_each(|s| sizes[*s] += 1);
+            let adjusted = fitness
+                .iter()
+                .zip(species.iter())
+                .map(|(f, s)| f / sizes[*s] as f64)
+                .collect();
+            (adjusted, count)
+        }
+    }
+}
+
+fn behaviour_distance(a: &Behaviour, b: &Behaviour) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (*x as f64 - *y as f64).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Novelty of every behaviour: the mean distance to its `k` nearest
+/// neighbours among the other behaviours and the archive.
+pub fn novelty(
+    behaviours: &[Behaviour],
+    archive: &[Behaviour],
+    k: usize,
+    workers: usize,
+) -> Vec<f64> {
+    let indices: Vec<usize> = (0..behaviours.len()).collect();
+    par_map(&indices, workers, |i| {
+        let mut distances: Vec<f64> = behaviours
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| j != i)
+            .map(|(_, b)| b)
+            .chain(archive)
+            .map(|b| behaviour_distance(&behaviours[*i], b))
+            .collect();
+        let k = k.clamp(1, distances.len().max(1));
+        if distances.len() > k {
+            distances.select_nth_unstable_by(k - 1, f64::total_cmp);
+            distances.truncate(k);
+        }
+        distances.iter().sum::<f64>() / distances.len().max(1) as f64
+    })
+}
+
+/// Blends normalised fitness and novelty: `weight` 0 selects on fitness
+/// only, 1 on novelty only.
+pub fn blend(fitness: &[f64], novelty: &[f64], weight: f64) -> Vec<f64> {
+    let max = |v: &[f64]| v.iter().copied().fold(0.0, f64::max).max(1e-12);
+    let (max_fitness, max_novelty) = (max(fitness), max(novelty));
+    fitness
+        .iter()
+        .zip(novelty)
+        .map(|(f, n)| (1.0 - weight) * f / max_fitness + weight * n / max_novelty)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::genetic::{Selection, Selector};
+    use crate::nn::Network;
+    use rand::prelude::*;
+
+    #[test]
+    fn parse() {
+        for s in ["none", "sharing:0.05", "species:0.1"] {
+            assert_eq!(s.parse::<Niching>().unwrap().to_string(), s);
+        }
+        assert!("sharing".parse::<Niching>().is_err());
+        assert!("clusters:3".parse::<Niching>().is_err());
+    }
+
+    #[test]
+    fn niching() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let a = Network::new(&mut rng).dump();
+        let b = Network::new(&mut rng).dump();
+        assert_eq!(distance(&a, &a), 0.0);
+        assert!((distance(&a, &b) - (2f64 / 3.0).sqrt()).abs() < 0.05);
+
+        // three near copies of `a` and one `b`
+        let mut close = a.clone();
+        close[0] += 0.5;
+        let genomes = vec![a.clone(), b.clone(), close, a.clone()];
+        let fitness = [4.0, 3.0, 2.0, 1.0];
+        assert_eq!(species(&genomes, 0.1), [0, 1, 0, 0]);
+        let (adjusted, count) = niche(Niching::Species { threshold: 0.1 }, &fitness, &genomes, 2);
+        assert_eq!(count, 2);
+        assert_eq!(adjusted, [4.0 / 3.0, 3.0, 2.0 / 3.0, 1.0 / 3.0]);
+        let (shared, _) = niche(Niching::Sharing { radius: 0.1 }, &fitness, &genomes, 2);
+        assert_eq!(shared[1], 3.0);
+        assert!(shared[0] < 4.0 / 2.5 && shared[0] > 4.0 / 3.0);
+        assert!(mean_distance(&genomes, 4) > mean_distance(&genomes[2..], 4));
+
+        // the lone `b` now leads, and the parents follow the niched scores
+        for selection in [
+            Selection::Roulette,
+            Selection::Rank,
+            Selection::Tournament(2),
+        ] {
+            let selector = Selector::new(selection, &adjusted);
+            let mut counts = [0; 4];
+            for _ in 0..4000 {
+                counts[selector.sample(&mut rng)] += 1;
+            }
+            let most = (0..4).max_by_key(|i| counts[*i]).unwrap();
+            assert_eq!(most, 1, "{} {:?}", selection, counts);
+        }
+    }
+
+    #[test]
+    fn novelty_search() {
+        let mut behaviours = [[0.0; BEHAVIOUR]; 4];
+        behaviours[3][0] = 1.0;
+        let scores = novelty(&behaviours, &[], 2, 2);
+        assert_eq!(scores, [0.0, 0.0, 0.0, 1.0]);
+        // an archived twin makes the outlier ordinary
+        let scores = novelty(&behaviours, &[behaviours[3]], 1, 1);
+        assert_eq!(scores[3], 0.0);
+        assert_eq!(blend(&[2.0, 1.0], &[0.0, 4.0], 0.5), [0.5, 0.75]);
+    }
+}
@@ -1,134 +1,171 @@
-use once_cell::sync::Lazy;
 use rand::prelude::*;
 use std::arch::x86_64::_pext_u64;
 use std::fmt;
 use std::mem::transmute;
+use std::str::FromStr;
 
 use crate::nn::T8;
 
-fn u16_to_data(d: u16) -> [i32; 4] {
-    let mut data = [0; 4];
-    for i in 0..4 {
-        data[i] = ((d >> (i * 4)) & 0xf) as i32;
+/// A line after a move towards its low bits, the first tile being the
+/// lowest nibble. Tiles stop at 15.
+const fn move_line(line: u16) -> u16 {
+    let mut res = 0;
+    let mut shift = 0;
+    let mut last = 0;
+    let mut i = 0;
+    while i < 16 {
+        let tile = (line >> i) & 0xf;
+        if tile != 0 {
+            if tile == last {
+                if tile != 15 {
+                    res += 1 << (shift - 4);
+                }
+                last = 0;
+            } else {
+                res |= tile << shift;
+                shift += 4;
+                last = tile;
+            }
+        }
+        i += 4;
     }
-    data
+    res
 }
 
-fn data_to_u16(data: &[i32; 4]) -> u16 {
-    let mut d = 0;
-    for i in 0..4 {
-        d |= (data[i] as u16) << (i * 4);
-    }
-    d
+const fn reverse(line: u16) -> u16 {
+    (line >> 12) | ((line >> 4) & 0xf0) | ((line << 4) & 0xf00) | (line << 12)
 }
 
-fn data_to_u64(data: &[i32; 4]) -> u64 {
-    let mut d = 0;
-    for i in 0..4 {
-        d |= (data[i] as u64) << (i * 16);
-    }
-    d
+/// A row as a column: tile `i` goes to bits `16 * i`.
+const fn to_col(line: u16) -> u64 {
+    let line = line as u64;
+    (line & 0xf) | ((line & 0xf0) << 12) | ((line & 0xf00) << 24) | ((line & 0xf000) << 36)
 }
 
-fn calc_line_l(data: &[i32; 4]) -> [i32; 4] {
-    let mut res = [0; 4];
-    let mut index = 0;
-    let mut flag = false;
-    for num in data.iter() {
-        if *num != 0 {
-            if flag && res[index - 1] == *num {
-                if res[index - 1] != 15 {
-                    res[index - 1] += 1;
-                }
-                flag = false;
+/// Merge points of one line. Merges only happen inside runs of equal tiles,
+/// so both directions of a line score the same.
+const fn calc_points(line: u16) -> u32 {
+    let mut points = 0;
+    let mut last = 0;
+    let mut i = 0;
+    while i < 16 {
+        let tile = (line >> i) & 0xf;
+        if tile != 0 {
+            if tile == last {
+                points += 1 << (tile + 1);
+                last = 0;
             } else {
-                res[index] = *num;
-                index += 1;
-                flag = true;
+                last = tile;
             }
         }
+        i += 4;
     }
-    res
+    points
 }
 
-fn calc_line_r(data: &[i32; 4]) -> [i32; 4] {
-    let mut res = [0; 4];
-    let mut index = 4;
-    let mut flag = false;
-    for num in data.iter().rev() {
-        if *num != 0 {
-            if flag && res[index] == *num {
-                if res[index] != 15 {
-                    res[index] += 1;
-                }
-                flag = false;
-            } else {
-                res[index - 1] = *num;
-                index -= 1;
-                flag = true;
-            }
-        }
-    }
-    res
+const fn free(line: u16) -> usize {
+    let tiles = line | (line >> 1) | (line >> 2) | (line >> 3);
+    4 - (tiles & 0x1111).count_ones() as usize
 }
 
+#[derive(Clone, Copy)]
 struct RowData {
     right: u16,
     left: u16,
     free: usize,
+    points: u32,
 }
 
-impl RowData {
-    fn new(data_r: &[i32; 4], data_l: &[i32; 4]) -> Self {
-        RowData {
-            right: data_to_u16(data_r),
-            left: data_to_u16(data_l),
-            free: data_r.iter().filter(|&x| *x == 0).count(),
-        }
-    }
-}
-
+#[derive(Clone, Copy)]
 struct ColData {
     up: u64,
     down: u64,
     free: usize,
+    points: u32,
 }
 
-impl ColData {
-    fn new(data_u: &[i32; 4], data_d: &[i32; 4]) -> Self {
-        ColData {
-            up: data_to_u64(data_u),
-            down: data_to_u64(data_d),
-            free: data_u.iter().filter(|&x| *x == 0).count(),
-        }
+/// Moves of every line, indexed by its 16 bits. Built by the compiler.
+#[allow(long_running_const_eval)]
+static TABLE: ([RowData; 65536], [ColData; 65536]) = calc_table();
+
+const fn calc_table() -> ([RowData; 65536], [ColData; 65536]) {
+    // each line moved once, a move to the right is the reversed line moved
+    let mut lefts = [0u16; 65536];
+    let mut i = 0;
+    while i < 65536 {
+        lefts[i] = move_line(i as u16);
+        i += 1;
+    }
+    let mut row_data = [RowData {
+        right: 0,
+        left: 0,
+        free: 0,
+        points: 0,
+    }; 65536];
+    let mut col_data = [ColData {
+        up: 0,
+        down: 0,
+        free: 0,
+        points: 0,
+    }; 65536];
+    let mut i = 0;
+    while i < 65536 {
+        let left = lefts[i];
+        let right = reverse(lefts[reverse(i as u16) as usize]);
+        let free = free(left);
+        let points = calc_points(i as u16);
+        row_data[i] = RowData {
+            right,
+            left,
+            free,
+            points,
+        };
+        col_data[i] = ColData {
+            up: to_col(left),
+            down: to_col(right),
+            free,
+            points,
+        };
+        i += 1;
     }
+    (row_data, col_data)
 }
 
-fn calc_line(data: &[i32; 4]) -> (RowData, ColData) {
-    let data_l = calc_line_l(data);
-    let data_r = calc_line_r(data);
-    (
-        RowData::new(&data_r, &data_l),
-        ColData::new(&data_l, &data_r),
-    )
-}
+/// Network input of every row, indexed by its 16 bits.
+#[allow(long_running_const_eval)]
+static F_TABLE: [[f32; 4]; 65536] = calc_f_table();
 
-static TABLE: Lazy<(Vec<RowData>, Vec<ColData>)> = Lazy::new(|| {
-    let mut row_data: Vec<RowData> = Vec::with_capacity(65536);
-    let mut col_data: Vec<ColData> = Vec::with_capacity(65536);
-    for i in 0..=65535u16 {
-        let data = u16_to_data(i);
-        let (row, col) = calc_line(&data);
-        row_data.push(row);
-        col_data.push(col);
+const fn calc_f_table() -> [[f32; 4]; 65536] {
+    let mut f = [0f32; 16];
+    let mut i = 0;
+    while i < 16 {
+        f[i] = i as f32 / 15f32;
+        i += 1;
     }
-    (row_data, col_data)
-});
+    let mut f_table = [[0f32; 4]; 65536];
+    let mut i = 0;
+    while i < 65536 {
+        f_table[i] = [f[i & 0xf], f[(i >> 4) & 0xf], f[(i >> 8) & 0xf], f[i >> 12]];
+        i += 1;
+    }
+    f_table
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Data(pub u64);
 
+impl Data {
+    /// Largest tile as an exponent, 11 for 2048.
+    pub fn max_tile(&self) -> u32 {
+        (0..16)
+            .map(|i| ((self.0 >> (i * 4)) & 0xf) as u32)
+            .max()
+            .unwrap()
+    }
+}
+
 impl fmt::Display for Data {
+    #[allow(clippy::needless_range_loop, clippy::write_with_newline)]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut data = [0i32; 16];
         for i in 0..16 {
@@ -148,32 +185,57 @@ impl fmt::Display for Data {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-struct Seed {
-    seed: usize,
-    value: u8,
-}
+impl Data {
+    /// Exponent of the tile at `row`, `col`, 0 for an empty cell.
+    pub fn tile(&self, row: usize, col: usize) -> u32 {
+        ((self.0 >> ((row * 4 + col) * 4)) & 0xf) as u32
+    }
 
-static mut SEED_TABLE: Lazy<Vec<Seed>> = Lazy::new(|| Vec::new());
-pub fn set_seed(mut seed: u64) {
-    let count = 100_000;
-    let mut seed_table: Vec<Seed> = Vec::with_capacity(count);
-    for _ in 0..count {
-        let seed_data = Seed {
-            seed: seed as usize,
-            value: if seed & 0x10 == 0 { 1 } else { 2 },
-        };
-        seed_table.push(seed_data);
-        seed = seed * seed % 50515093;
+    /// One-line notation: the exponents of the tiles in hex, row by row from
+    /// the top left, e.g. `0000/0000/0000/1200` for a 2 and a 4 on the
+    /// bottom row. Parsed back by `FromStr`.
+    pub fn notation(&self) -> String {
+        let rows: Vec<String> = (0..4)
+            .map(|row| {
+                (0..4)
+                    .map(|col| format!("{:x}", self.tile(row, col)))
+                    .collect()
+            })
+            .collect();
+        rows.join("/")
     }
-    unsafe {
-        *SEED_TABLE = seed_table;
+}
+
+impl FromStr for Data {
+    type Err = String;
+
+    /// Parses `Data::notation`. The `/` between rows are optional.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let digits: Vec<char> = s.chars().filter(|c| *c != '/').collect();
+        if digits.len() != 16 {
+            return Err(format!(
+                "invalid board {:?} (expected 16 hex digits, e.g. 0000/0000/0000/1200)",
+                s
+            ));
+        }
+        let mut data = 0;
+        for (i, c) in digits.iter().enumerate() {
+            let tile = c
+                .to_digit(16)
+                .ok_or_else(|| format!("invalid tile {:?} in board {:?}", c, s))?;
+            data |= (tile as u64) << (i * 4);
+        }
+        Ok(Data(data))
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Modulus of the reference spawn generator: every spawn uses the current
+/// seed and then squares it modulo this value.
+pub const SEED_MOD: u64 = 50515093;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Board {
-    pub turn: usize,
+    pub seed: u64,
     pub data: Data,
 }
 
@@ -182,37 +244,89 @@ pub struct Moves {
     pub right: Data,
     pub left: Data,
     pub free_rl: usize,
+    pub points_rl: u32,
     pub up: Data,
     pub down: Data,
     pub free_ud: usize,
+    pub points_ud: u32,
 }
 
-const COL_MASK: u64 = 15 | (15 << 16) | (15 << 32) | (15 << 48);
-const COL_MASKS: [u64; 4] = [COL_MASK, COL_MASK << 4, COL_MASK << 8, COL_MASK << 12];
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
 
-static F_TABLE: Lazy<Vec<[f32; 4]>> = Lazy::new(|| {
-    let mut f_table = Vec::with_capacity(65536);
-    for i in 0..=65535u16 {
-        let data = u16_to_data(i);
-        let mut f = [0f32; 4];
-        for j in 0..4 {
-            f[j] = data[j] as f32 / 15f32;
+impl Direction {
+    /// Every direction, in the order the search prefers on ties: down and
+    /// right first keep the tiles in the bottom right corner.
+    pub const ALL: [Direction; 4] = [
+        Direction::Down,
+        Direction::Right,
+        Direction::Up,
+        Direction::Left,
+    ];
+
+    pub fn to_char(self) -> char {
+        match self {
+            Direction::Up => 'U',
+            Direction::Down => 'D',
+            Direction::Left => 'L',
+            Direction::Right => 'R',
         }
-        f_table.push(f);
     }
-    f_table
-});
+
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'U' => Some(Direction::Up),
+            'D' => Some(Direction::Down),
+            'L' => Some(Direction::Left),
+            'R' => Some(Direction::Right),
+            _ => None,
+        }
+    }
+}
+
+impl Moves {
+    /// Tiles, free cells and merge points after moving `dir`.
+    pub fn get(&self, dir: Direction) -> (Data, usize, u32) {
+        match dir {
+            Direction::Up => (self.up, self.free_ud, self.points_ud),
+            Direction::Down => (self.down, self.free_ud, self.points_ud),
+            Direction::Left => (self.left, self.free_rl, self.points_rl),
+            Direction::Right => (self.right, self.free_rl, self.points_rl),
+        }
+    }
+}
+
+const COL_MASK: u64 = 15 | (15 << 16) | (15 << 32) | (15 << 48);
+const COL_MASKS: [u64; 4] = [COL_MASK, COL_MASK << 4, COL_MASK << 8, COL_MASK << 12];
 
 impl Board {
-    pub fn new() -> Self {
+    pub fn new(seed: u64) -> Self {
         let s0 = Self {
-            turn: 0,
+            seed: seed % SEED_MOD,
             data: Data(0),
         };
         let s1 = s0.spawn(Data(0), 16);
         s1.spawn(s1.data, 15)
     }
 
+    /// `data` about to get the spawn of `seed`, reduced as in `Board::new`.
+    pub fn from_data(seed: u64, data: Data) -> Self {
+        Self {
+            seed: seed % SEED_MOD,
+            data,
+        }
+    }
+
+    #[allow(
+        clippy::clone_on_copy,
+        clippy::identity_op,
+        clippy::missing_transmute_annotations
+    )]
     pub fn to_t8(&self) -> [T8; 2] {
         unsafe {
             let t1 = [
@@ -236,6 +350,7 @@ impl Board {
     }
 
     #[target_feature(enable = "bmi2")]
+    #[allow(clippy::missing_safety_doc, clippy::needless_range_loop)]
     pub unsafe fn moves(&self) -> Moves {
         let mut right = 0u64;
         let mut left = 0u64;
@@ -243,6 +358,8 @@ impl Board {
         let mut down = 0u64;
         let mut free_rl = 0;
         let mut free_ud = 0;
+        let mut points_rl = 0;
+        let mut points_ud = 0;
 
         for i in 0..4 {
             let row = (self.data.0 >> (i * 16)) & 0xffff;
@@ -255,21 +372,25 @@ impl Board {
             down |= col_data.down << (i * 4);
             free_rl += row_data.free;
             free_ud += col_data.free;
+            points_rl += row_data.points;
+            points_ud += col_data.points;
         }
 
         Moves {
             right: Data(right),
             left: Data(left),
             free_rl,
+            points_rl,
             up: Data(up),
             down: Data(down),
             free_ud,
+            points_ud,
         }
     }
 
     pub fn spawn(&self, data: Data, free: usize) -> Self {
-        let seed = unsafe { SEED_TABLE.get_unchecked(self.turn) };
-        let index = seed.seed % free;
+        let index = (self.seed % free as u64) as usize;
+        let value = if self.seed & 0x10 == 0 { 1u64 } else { 2 };
         let mask = 0xf;
         let mut pos = 0;
         for x in 0..4 {
@@ -280,8 +401,8 @@ impl Board {
                 if mask & data.0 == 0 {
                     if pos == index {
                         return Self {
-                            turn: self.turn + 1,
-                            data: Data(data.0 | ((seed.value as u64) << shift)),
+                            seed: self.seed * self.seed % SEED_MOD,
+                            data: Data(data.0 | (value << shift)),
                         };
                     }
                     pos += 1;
@@ -290,12 +411,28 @@ impl Board {
         }
         panic!("{} {}", index, pos);
     }
+
+    /// The board after moving `dir` and its spawn, `None` if the move
+    /// changes nothing.
+    pub fn apply(&self, dir: Direction) -> Option<Board> {
+        let (data, free, _) = unsafe { self.moves() }.get(dir);
+        (data != self.data).then(|| self.spawn(data, free))
+    }
+
+    /// Every move that changes the board, in the order of `Direction::ALL`,
+    /// with the board after it and its merge points.
+    pub fn successors(&self) -> impl Iterator<Item = (Direction, Board, u32)> + '_ {
+        let moves = unsafe { self.moves() };
+        Direction::ALL.into_iter().filter_map(move |dir| {
+            let (data, free, points) = moves.get(dir);
+            (data != self.data).then(|| (dir, self.spawn(data, free), points))
+        })
+    }
 }
 
-pub fn random_seed() {
+pub fn random_seed() -> u64 {
     let mut rng = thread_rng();
-    let seed = rng.gen::<u64>() % 50515093;
-    set_seed(seed);
+    rng.gen::<u64>() % SEED_MOD
 }
 
 #[cfg(test)]
@@ -303,16 +440,152 @@ mod test {
     use super::*;
     #[test]
     fn test() {
-        set_seed(123456);
-        let board = Board::new();
+        let board = Board::new(123456);
         println!("{}", board.data);
         println!("{:?}", board.to_t8());
     }
 
+    #[test]
+    fn large_seed() {
+        // squaring a seed of 2^32 or more would overflow
+        let board = Board::new(u64::MAX);
+        assert_eq!(board, Board::new(u64::MAX % SEED_MOD));
+        assert!(board.seed < SEED_MOD);
+        // as `play --board` builds it
+        let board = Board::from_data(u64::MAX, Data(0x1200));
+        assert_eq!(board.seed, u64::MAX % SEED_MOD);
+        let spawned = board.spawn(board.data, 14);
+        assert_ne!(spawned.data, board.data);
+        assert!(spawned.seed < SEED_MOD);
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(move_line(0x1111), 0x0022);
+        assert_eq!(move_line(0x2101), 0x0022);
+        assert_eq!(move_line(0x2121), 0x2121);
+        // tiles stop at 32768, two of them merge into one
+        assert_eq!(move_line(0x00ff), 0x000f);
+        assert_eq!(reverse(0x4321), 0x1234);
+        let row = &TABLE.0[0x2101];
+        assert_eq!((row.left, row.right, row.free), (0x0022, 0x2200, 2));
+        let col = &TABLE.1[0x2101];
+        assert_eq!((col.up, col.down), (0x0002_0002, 0x0002_0002 << 32));
+        assert_eq!(F_TABLE[0xf010], [0.0, 1.0 / 15.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn directions() {
+        for dir in Direction::ALL {
+            assert_eq!(Direction::from_char(dir.to_char()), Some(dir));
+        }
+        let chars: String = Direction::ALL.iter().map(|d| d.to_char()).collect();
+        assert_eq!(chars, "DRUL");
+        assert_eq!(Direction::from_char('X'), None);
+
+        let board = Board {
+            seed: 1,
+            data: "1100/0000/0000/0000".parse().unwrap(),
+        };
+        assert_eq!(board.apply(Direction::Up), None);
+        let left = board.apply(Direction::Left).unwrap();
+        assert_eq!(left.data.notation(), "2000/0000/1000/0000");
+        let successors: Vec<_> = board.successors().collect();
+        let dirs: Vec<Direction> = successors.iter().map(|(d, _, _)| *d).collect();
+        assert_eq!(dirs, [Direction::Down, Direction::Right, Direction::Left]);
+        assert_eq!(successors[2], (Direction::Left, left, 4));
+    }
+
+    #[test]
+    fn notation() {
+        let data = Data(0x0123_0000_0011_f000);
+        assert_eq!(data.notation(), "000f/1100/0000/3210");
+        assert_eq!(data.tile(3, 0), 3);
+        assert_eq!("000f/1100/0000/3210".parse::<Data>(), Ok(data));
+        assert_eq!("000F110000003210".parse::<Data>(), Ok(data));
+        assert!("000f/1100/0000".parse::<Data>().is_err());
+        assert!("000g/1100/0000/3210".parse::<Data>().is_err());
+    }
+
+    #[test]
+    fn points() {
+        assert_eq!(calc_points(0x1111), 8);
+        assert_eq!(calc_points(0x2101), 4);
+        assert_eq!(calc_points(0x2121), 0);
+        // as in the reference, though the engine keeps one 32768
+        assert_eq!(calc_points(0x00ff), 65536);
+        let board = Board {
+            seed: 1,
+            data: Data(0x0000_0000_0000_1111),
+        };
+        let moves = unsafe { board.moves() };
+        assert_eq!(moves.points_rl, 8);
+        assert_eq!(moves.points_ud, 0);
+        assert_eq!(moves.left.max_tile(), 2);
+    }
+
+    /// A trace board as the engine holds it: the 65536 (`g`) of two merged
+    /// 32768s stays a 32768, though the merge scores its 65536 points.
+    fn parse(board: &str) -> Data {
+        board.replace('g', "f").parse().unwrap()
+    }
+
+    fn apply(board: &Board, dir: &str) -> Option<(Board, u32)> {
+        let dir = Direction::from_char(dir.chars().next().unwrap()).unwrap();
+        let (_, next, points) = board.successors().find(|(d, _, _)| *d == dir)?;
+        assert_eq!(board.apply(dir), Some(next));
+        Some((next, points))
+    }
+
+    #[test]
+    fn golden() {
+        let traces = include_str!("../../golden/traces.txt");
+        let mut game = None;
+        for (i, line) in traces.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [] => {}
+                ["#", ..] => {}
+                ["new", seed, next, board] => {
+                    let board_new = Board::new(seed.parse().unwrap());
+                    assert_eq!(board_new.data, parse(board), "line {}", i + 1);
+                    assert_eq!(
+                        board_new.seed,
+                        next.parse::<u64>().unwrap(),
+                        "line {}",
+                        i + 1
+                    );
+                    game = Some((board_new, 0));
+                }
+                ["game", seed, board] => {
+                    let board = Board {
+                        seed: seed.parse().unwrap(),
+                        data: parse(board),
+                    };
+                    game = Some((board, 0));
+                }
+                ["over"] => {
+                    let (board, _) = game.as_ref().unwrap();
+                    assert_eq!(board.successors().count(), 0, "line {}", i + 1);
+                }
+                [dir, seed, score, board] => {
+                    let (board_before, points) = game.as_mut().unwrap();
+                    let (next, p) = apply(board_before, dir).unwrap();
+                    *points += p as u64;
+                    assert_eq!(next.data, parse(board), "line {}", i + 1);
+                    assert_eq!(next.seed, seed.parse::<u64>().unwrap(), "line {}", i + 1);
+                    assert_eq!(*points, score.parse::<u64>().unwrap(), "line {}", i + 1);
+                    *board_before = next;
+                }
+                _ => panic!("line {}: {:?}", i + 1, line),
+            }
+        }
+        assert!(game.is_some());
+    }
+
     #[test]
     fn test2() {
-        random_seed();
-        let board = Board::new();
+        let board = Board::new(random_seed());
         println!("{}", board.data);
     }
 }
@@ -0,0 +1,427 @@
+//! Benchmarks one network over a list of seeds.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::engine::Board;
+use crate::format::{FormatError, Population};
+use crate::game::Game;
+use crate::nn::Network;
+use crate::npz;
+use crate::progress::Pbar;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Agent {
+    /// One ply, like `Game::run`.
+    Greedy,
+    /// Full-width search, like `Game::run_with_ai`.
+    Search(usize),
+}
+
+impl Agent {
+    pub fn parse(name: &str, depth: usize) -> Result<Self, String> {
+        match name {
+            "greedy" => Ok(Agent::Greedy),
+            "search" if depth > 0 => Ok(Agent::Search(depth)),
+            "search" => Err("search depth must be at least 1".to_string()),
+            _ => Err(format!(
+                "unknown agent {} (expected greedy or search)",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Agent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Agent::Greedy => write!(f, "greedy"),
+            Agent::Search(depth) => write!(f, "search depth {}", depth),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameResult {
+    pub seed: u64,
+    pub moves: usize,
+    pub points: usize,
+    pub max_tile: u32,
+}
+
+pub fn play(network: &Network, agent: Agent, seed: u64) -> GameResult {
+    let mut game = Game::new(*network);
+    let board = match agent {
+        Agent::Greedy => game.run(seed),
+        Agent::Search(depth) => game.run_with_ai(depth, seed),
+    };
+    GameResult {
+        seed,
+        moves: game.score,
+        points: game.points,
+        max_tile: board.data.max_tile(),
+    }
+}
+
+/// Maps `items` on `workers` scoped threads that pull indices from a shared
+/// counter. Each result lands at its item's index, so the output does not
+/// depend on the worker count or on scheduling.
+pub fn par_map<T, R, F>(items: &[T], workers: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let index = AtomicUsize::new(0);
+    let pbar = Pbar::new(items.len());
+    let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..workers.max(1))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = index.fetch_add(1, Ordering::Relaxed);
+                        if i >= items.len() {
+                            break done;
+                        }
+                        done.push((i, f(&items[i])));
+                        pbar.inc(1);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            for (i, result) in handle.join().unwrap() {
+                results[i] = Some(result);
+            }
+        }
+    });
+    pbar.finish();
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+/// Plays every seed on `workers` threads. Results are in `seeds` order.
+pub fn evaluate(network: &Network, agent: Agent, seeds: &[u64], workers: usize) -> Vec<GameResult> {
+    par_map(seeds, workers, |seed| play(network, agent, *seed))
+}
+
+/// Parses `1,2,3`, `0..100`, `0..=99` or a comma separated mix of them.
+pub fn parse_seeds(s: &str) -> Result<Vec<u64>, String> {
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u64>()
+            .map_err(|_| format!("invalid seed {:?}", v))
+    };
+    let mut seeds = Vec::new();
+    for part in s.split(',').filter(|p| !p.trim().is_empty()) {
+        if let Some((start, end)) = part.split_once("..=") {
+            seeds.extend(parse(start)?..=parse(end)?);
+        } else if let Some((start, end)) = part.split_once("..") {
+            seeds.extend(parse(start)?..parse(end)?);
+        } else {
+            seeds.push(parse(part)?);
+        }
+    }
+    if seeds.is_empty() {
+        return Err("no seeds given".to_string());
+    }
+    Ok(seeds)
+}
+
+/// Loads a population file or an `.npz` export.
+pub fn load_population(path: &Path) -> Result<Population, FormatError> {
+    if path.extension().is_some_and(|e| e == "npz") {
+        npz::import_population(&std::fs::read(path)?)
+    } else {
+        Population::read(path)
+    }
+}
+
+/// How the per-seed scores of one bot are folded into its fitness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    Mean,
+    Min,
+    /// Nearest-rank quantile, `0.0..=1.0`.
+    Quantile(f64),
+}
+
+impl Aggregate {
+    pub fn apply(self, values: &[f64]) -> f64 {
+        match self {
+            Aggregate::Mean => values.iter().sum::<f64>() / values.len().max(1) as f64,
+            Aggregate::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregate::Quantile(q) => quantile(values, q),
+        }
+    }
+}
+
+impl FromStr for Aggregate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.split_once(':') {
+            None if s == "mean" => Ok(Aggregate::Mean),
+            None if s == "min" => Ok(Aggregate::Min),
+            Some(("quantile", q)) => match q.parse::<f64>() {
+                Ok(q) if (0.0..=1.0).contains(&q) => Ok(Aggregate::Quantile(q)),
+                _ => Err(format!("invalid quantile {:?} (expected 0..=1)", q)),
+            },
+            _ => Err(format!(
+                "unknown aggregate {} (expected mean, min or quantile:<q>)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Aggregate::Mean => write!(f, "mean"),
+            Aggregate::Min => write!(f, "min"),
+            Aggregate::Quantile(q) => write!(f, "quantile:{}", q),
+        }
+    }
+}
+
+/// What one game is worth to the fitness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fitness {
+    /// Moves survived.
+    Moves,
+    /// Merge points, what CodinGame ranks by.
+    Score,
+    /// Value of the largest tile.
+    MaxTile,
+}
+
+impl Fitness {
+    pub fn value(self, moves: usize, points: usize, board: &Board) -> f64 {
+        match self {
+            Fitness::Moves => moves as f64,
+            Fitness::Score => points as f64,
+            Fitness::MaxTile => (1u64 << board.data.max_tile()) as f64,
+        }
+    }
+}
+
+impl FromStr for Fitness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "moves" => Ok(Fitness::Moves),
+            "score" => Ok(Fitness::Score),
+            "max-tile" => Ok(Fitness::MaxTile),
+            _ => Err(format!(
+                "unknown fitness {} (expected moves, score or max-tile)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Fitness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fitness::Moves => write!(f, "moves"),
+            Fitness::Score => write!(f, "score"),
+            Fitness::MaxTile => write!(f, "max-tile"),
+        }
+    }
+}
+
+/// How bots search and what their games are worth during training.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Objective {
+    pub depth: usize,
+    pub aggregate: Aggregate,
+    pub fitness: Fitness,
+    /// See `Game::points_weight`.
+    pub points_weight: f32,
+}
+
+/// Nearest-rank quantile, zero for no values.
+pub fn quantile(values: &[f64], q: f64) -> f64 {
+    let mut values = values.to_vec();
+    values.sort_by(f64::total_cmp);
+    quantile_sorted(&values, q)
+}
+
+fn quantile_sorted(values: &[f64], q: f64) -> f64 {
+    let n = values.len();
+    if n == 0 {
+        return 0.0;
+    }
+    let rank = ((q * n as f64).ceil() as usize).clamp(1, n);
+    values[rank - 1]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub mean: f64,
+    pub min: f64,
+    pub p10: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub p90: f64,
+    pub max: f64,
+}
+
+impl Summary {
+    pub fn new(values: impl IntoIterator<Item = f64>) -> Self {
+        let mut values: Vec<f64> = values.into_iter().collect();
+        values.sort_by(f64::total_cmp);
+        let at = |q: f64| quantile_sorted(&values, q);
+        Summary {
+            mean: Aggregate::Mean.apply(&values),
+            min: at(0.0),
+            p10: at(0.1),
+            p25: at(0.25),
+            median: at(0.5),
+            p75: at(0.75),
+            p90: at(0.9),
+            max: values.last().copied().unwrap_or(0.0),
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mean {:>10.1}  min {:>8}  p10 {:>8}  p25 {:>8}  median {:>8}  p75 {:>8}  p90 {:>8}  max {:>8}",
+            self.mean, self.min, self.p10, self.p25, self.median, self.p75, self.p90, self.max
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub games: usize,
+    pub moves: Summary,
+    pub points: Summary,
+    /// `(exponent, share of games whose max tile reached it)` from 2048 up.
+    pub tiles: Vec<(u32, f64)>,
+    pub elapsed: Duration,
+    pub total_moves: usize,
+}
+
+impl Report {
+    pub fn new(results: &[GameResult], elapsed: Duration) -> Self {
+        let games = results.len();
+        let top = results.iter().map(|r| r.max_tile).max().unwrap_or(0);
+        let tiles = (11..=top.max(11))
+            .map(|t| {
+                let reached = results.iter().filter(|r| r.max_tile >= t).count();
+                (t, reached as f64 / games.max(1) as f64)
+            })
+            .collect();
+        Report {
+            games,
+            moves: Summary::new(results.iter().map(|r| r.moves as f64)),
+            points: Summary::new(results.iter().map(|r| r.points as f64)),
+            tiles,
+            elapsed,
+            total_moves: results.iter().map(|r| r.moves).sum(),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "games  {}", self.games)?;
+        writeln!(f, "moves  {}", self.moves)?;
+        writeln!(f, "score  {}", self.points)?;
+        for (tile, rate) in self.tiles.iter() {
+            writeln!(f, "{:>6} {:>6.1}%", 1u32 << tile, rate * 100.0)?;
+        }
+        let secs = self.elapsed.as_secs_f64().max(1e-9);
+        write!(
+            f,
+            "{:.1}s, {:.2} games/s, {:.0} moves/s",
+            secs,
+            self.games as f64 / secs,
+            self.total_moves as f64 / secs
+        )
+    }
+}
+
+/// Plays `seeds` and builds the report.
+pub fn benchmark(network: &Network, agent: Agent, seeds: &[u64], workers: usize) -> Report {
+    let start = Instant::now();
+    let results = evaluate(network, agent, seeds, workers);
+    Report::new(&results, start.elapsed())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::SEED_MOD;
+    use rand::prelude::*;
+
+    #[test]
+    fn seeds() {
+        assert_eq!(parse_seeds("1,2,3").unwrap(), vec![1, 2, 3]);
+        assert_eq!(parse_seeds("0..3").unwrap(), vec![0, 1, 2]);
+        assert_eq!(parse_seeds("0..=3,7").unwrap(), vec![0, 1, 2, 3, 7]);
+        assert!(parse_seeds("a..3").is_err());
+        assert!(parse_seeds("").is_err());
+
+        // seeds of 2^32 and more play like their remainder
+        let seeds = parse_seeds("18446744073709551615").unwrap();
+        let network = Network::new(&mut StdRng::seed_from_u64(1));
+        let large = play(&network, Agent::Greedy, seeds[0]);
+        let reduced = play(&network, Agent::Greedy, u64::MAX % SEED_MOD);
+        assert_eq!((large.seed, large.moves), (u64::MAX, reduced.moves));
+    }
+
+    #[test]
+    fn summary() {
+        let s = Summary::new((1..=10).map(|x| x as f64));
+        assert_eq!(s.mean, 5.5);
+        assert_eq!(s.median, 5.0);
+        assert_eq!(s.p90, 9.0);
+        assert_eq!(s.min, 1.0);
+        assert_eq!(s.max, 10.0);
+    }
+
+    #[test]
+    fn aggregate() {
+        let values = [4.0, 1.0, 3.0, 2.0];
+        assert_eq!(Aggregate::Mean.apply(&values), 2.5);
+        assert_eq!(Aggregate::Min.apply(&values), 1.0);
+        assert_eq!(Aggregate::Quantile(0.25).apply(&values), 1.0);
+        assert_eq!(Aggregate::Quantile(0.5).apply(&values), 2.0);
+        for s in ["mean", "min", "quantile:0.1"] {
+            assert_eq!(s.parse::<Aggregate>().unwrap().to_string(), s);
+        }
+        assert!("quantile:2".parse::<Aggregate>().is_err());
+        assert!("median".parse::<Aggregate>().is_err());
+        for s in ["moves", "score", "max-tile"] {
+            assert_eq!(s.parse::<Fitness>().unwrap().to_string(), s);
+        }
+        assert!("tiles".parse::<Fitness>().is_err());
+    }
+
+    #[test]
+    fn deterministic() {
+        let mut rng = thread_rng();
+        let network = Network::new(&mut rng);
+        let seeds = parse_seeds("1..9").unwrap();
+        let a = evaluate(&network, Agent::Greedy, &seeds, 1);
+        let b = evaluate(&network, Agent::Greedy, &seeds, 3);
+        assert_eq!(a, b);
+        assert_eq!(a.iter().map(|r| r.seed).collect::<Vec<_>>(), seeds);
+        let report = Report::new(&a, Duration::from_secs(1));
+        assert_eq!(report.games, 8);
+        assert_eq!(report.tiles[0].0, 11);
+    }
+}
@@ -0,0 +1,617 @@
+//! On-disk format for populations and single models.
+//!
+//! ```text
+//! magic "2048AIPF" | version u32 | section count u32 | sections.. | crc32 u32
+//! section: tag [u8; 4] | length u64 | payload
+//! ```
+//!
+//! Every number is little endian. The checksum covers every byte before it.
+//! Unknown sections are skipped on load so newer writers can add data
+//! without breaking older readers.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::nn::{LayerKind, LayerShape, Network};
+
+pub const MAGIC: [u8; 8] = *b"2048AIPF";
+pub const VERSION: u32 = 1;
+
+pub const TAG_TOPOLOGY: [u8; 4] = *b"TOPO";
+pub const TAG_META: [u8; 4] = *b"META";
+pub const TAG_BOTS: [u8; 4] = *b"BOTS";
+
+#[derive(Debug)]
+pub enum FormatError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    Checksum {
+        stored: u32,
+        computed: u32,
+    },
+    MissingSection([u8; 4]),
+    Invalid(&'static str),
+    Topology {
+        expected: Vec<LayerShape>,
+        found: Vec<(String, LayerKind, usize, usize)>,
+    },
+    Population {
+        expected: usize,
+        found: usize,
+    },
+    Npz(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Io(e) => write!(f, "io error: {}", e),
+            FormatError::BadMagic => write!(f, "not a 2048AI population file"),
+            FormatError::UnsupportedVersion(v) => {
+                write!(f, "unsupported version {} (newest known is {})", v, VERSION)
+            }
+            FormatError::Truncated => write!(f, "file is truncated"),
+            FormatError::Checksum { stored, computed } => write!(
+                f,
+                "checksum mismatch: stored {:08x}, computed {:08x}",
+                stored, computed
+            ),
+            FormatError::MissingSection(tag) => {
+                write!(f, "missing section {}", String::from_utf8_lossy(tag))
+            }
+            FormatError::Invalid(msg) => write!(f, "invalid data: {}", msg),
+            FormatError::Topology { expected, found } => {
+                write!(f, "topology mismatch: expected [")?;
+                for (i, l) in expected.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} {:?} {}x{}", l.name, l.kind, l.inputs, l.outputs)?;
+                }
+                write!(f, "], found [")?;
+                for (i, (name, kind, inputs, outputs)) in found.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} {:?} {}x{}", name, kind, inputs, outputs)?;
+                }
+                write!(f, "]")
+            }
+            FormatError::Population { expected, found } => write!(
+                f,
+                "population size mismatch: expected {}, found {}",
+                expected, found
+            ),
+            FormatError::Npz(msg) => write!(f, "npz: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<io::Error> for FormatError {
+    fn from(e: io::Error) -> Self {
+        FormatError::Io(e)
+    }
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// CRC-32 (IEEE 802.3), the same checksum zip and png use.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut c = !0u32;
+    for &b in data {
+        c = CRC32_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+#[derive(Debug, Default)]
+pub struct Writer(pub Vec<u8>);
+
+impl Writer {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, v: f32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, v: f64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn str(&mut self, v: &str) {
+        self.u32(v.len() as u32);
+        self.0.extend_from_slice(v.as_bytes());
+    }
+
+    pub fn f32s(&mut self, v: &[f32]) {
+        for x in v {
+            self.f32(*x);
+        }
+    }
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.data.len() - self.pos < len {
+            return Err(FormatError::Truncated);
+        }
+        let res = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(res)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+        let mut buf = [0u8; N];
+        buf.copy_from_slice(self.bytes(N)?);
+        Ok(buf)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, FormatError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, FormatError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, FormatError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, FormatError> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    pub fn str(&mut self) -> Result<String, FormatError> {
+        let len = self.u32()? as usize;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| FormatError::Invalid("string is not utf-8"))
+    }
+
+    pub fn f32s(&mut self, len: usize) -> Result<Vec<f32>, FormatError> {
+        // the bytes first, so a corrupt length cannot size the allocation
+        let bytes = self.bytes(len.checked_mul(4).ok_or(FormatError::Truncated)?)?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect())
+    }
+}
+
+/// The raw container: a list of tagged sections.
+#[derive(Debug, Clone, Default)]
+pub struct Sections(pub Vec<([u8; 4], Vec<u8>)>);
+
+impl Sections {
+    pub fn push(&mut self, tag: [u8; 4], data: Vec<u8>) {
+        self.0.push((tag, data));
+    }
+
+    pub fn get(&self, tag: [u8; 4]) -> Option<&[u8]> {
+        self.0
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, d)| d.as_slice())
+    }
+
+    pub fn require(&self, tag: [u8; 4]) -> Result<&[u8], FormatError> {
+        self.get(tag).ok_or(FormatError::MissingSection(tag))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.0.extend_from_slice(&MAGIC);
+        w.u32(VERSION);
+        w.u32(self.0.len() as u32);
+        for (tag, data) in self.0.iter() {
+            w.0.extend_from_slice(tag);
+            w.u64(data.len() as u64);
+            w.0.extend_from_slice(data);
+        }
+        let crc = crc32(&w.0);
+        w.u32(crc);
+        w.0
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        if data.len() < MAGIC.len() || data[..MAGIC.len()] != MAGIC {
+            return Err(FormatError::BadMagic);
+        }
+        if data.len() < MAGIC.len() + 12 {
+            return Err(FormatError::Truncated);
+        }
+        let (body, crc) = data.split_at(data.len() - 4);
+        let mut r = Reader::new(body);
+        r.bytes(MAGIC.len())?;
+        let version = r.u32()?;
+        if version == 0 || version > VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        let stored = u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]);
+        let computed = crc32(body);
+        if stored != computed {
+            return Err(FormatError::Checksum { stored, computed });
+        }
+        let count = r.u32()?;
+        let mut sections = Sections::default();
+        for _ in 0..count {
+            let mut tag = [0u8; 4];
+            tag.copy_from_slice(r.bytes(4)?);
+            let len = r.u64()?;
+            let len = usize::try_from(len).map_err(|_| FormatError::Truncated)?;
+            sections.push(tag, r.bytes(len)?.to_vec());
+        }
+        if !r.is_empty() {
+            return Err(FormatError::Invalid("trailing bytes after last section"));
+        }
+        Ok(sections)
+    }
+}
+
+pub fn encode_topology(topology: &[LayerShape]) -> Vec<u8> {
+    let mut w = Writer::new();
+    w.u32(topology.len() as u32);
+    for layer in topology {
+        w.str(layer.name);
+        w.u8(layer.kind.to_u8());
+        w.u32(layer.inputs as u32);
+        w.u32(layer.outputs as u32);
+    }
+    w.0
+}
+
+/// Checks a stored topology against the compiled `Network`.
+pub fn check_topology(data: &[u8]) -> Result<(), FormatError> {
+    let mut r = Reader::new(data);
+    let count = r.u32()?;
+    let mut found = Vec::new();
+    for _ in 0..count {
+        let name = r.str()?;
+        let kind = LayerKind::from_u8(r.u8()?).ok_or(FormatError::Invalid("unknown layer kind"))?;
+        let inputs = r.u32()? as usize;
+        let outputs = r.u32()? as usize;
+        found.push((name, kind, inputs, outputs));
+    }
+    let expected = Network::topology();
+    let same = expected.len() == found.len()
+        && expected
+            .iter()
+            .zip(found.iter())
+            .all(|(e, f)| e.name == f.0 && e.kind == f.1 && e.inputs == f.2 && e.outputs == f.3);
+    if same {
+        Ok(())
+    } else {
+        Err(FormatError::Topology { expected, found })
+    }
+}
+
+/// A population (or a single model, which is a population of one) together
+/// with its fitness values and training metadata. Sections other than the
+/// three core ones (optimiser state and the like) are kept in `extra`.
+#[derive(Debug, Clone)]
+pub struct Population {
+    pub generation: u64,
+    pub metadata: Vec<(String, String)>,
+    pub bots: Vec<(Network, f64)>,
+    pub extra: Sections,
+}
+
+impl Population {
+    pub fn to_sections(&self) -> Sections {
+        let mut sections = Sections::default();
+        sections.push(TAG_TOPOLOGY, encode_topology(&Network::topology()));
+
+        let mut w = Writer::new();
+        w.u64(self.generation);
+        w.u32(self.metadata.len() as u32);
+        for (key, value) in self.metadata.iter() {
+            w.str(key);
+            w.str(value);
+        }
+        sections.push(TAG_META, w.0);
+
+        let mut w = Writer::new();
+        w.u64(self.bots.len() as u64);
+        w.u64(Network::size() as u64);
+        for (network, fitness) in self.bots.iter() {
+            w.f64(*fitness);
+            w.f32s(&network.dump());
+        }
+        sections.push(TAG_BOTS, w.0);
+        sections.0.extend(self.extra.0.iter().cloned());
+        sections
+    }
+
+    pub fn from_sections(sections: &Sections) -> Result<Self, FormatError> {
+        check_topology(sections.require(TAG_TOPOLOGY)?)?;
+
+        let mut r = Reader::new(sections.require(TAG_META)?);
+        let generation = r.u64()?;
+        let count = r.u32()?;
+        let mut metadata = Vec::new();
+        for _ in 0..count {
+            let key = r.str()?;
+            let value = r.str()?;
+            metadata.push((key, value));
+        }
+
+        let mut r = Reader::new(sections.require(TAG_BOTS)?);
+        let count = r.u64()? as usize;
+        let params = r.u64()? as usize;
+        if params != Network::size() {
+            return Err(FormatError::Invalid(
+                "parameter count does not match topology",
+            ));
+        }
+        let mut bots = Vec::with_capacity(count.min(1 << 20));
+        for _ in 0..count {
+            let fitness = r.f64()?;
+            let weights = r.f32s(params)?;
+            bots.push((Network::load(&weights), fitness));
+        }
+        if !r.is_empty() {
+            return Err(FormatError::Invalid("trailing bytes in bot section"));
+        }
+        let core = [TAG_TOPOLOGY, TAG_META, TAG_BOTS];
+        let extra = Sections(
+            sections
+                .0
+                .iter()
+                .filter(|(tag, _)| !core.contains(tag))
+                .cloned()
+                .collect(),
+        );
+        Ok(Population {
+            generation,
+            metadata,
+            bots,
+            extra,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.to_sections().encode()
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        Self::from_sections(&Sections::decode(data)?)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, FormatError> {
+        Self::decode(&std::fs::read(path)?)
+    }
+
+    /// Index of the bot with the highest fitness.
+    pub fn best(&self) -> Option<usize> {
+        (0..self.bots.len()).max_by(|&a, &b| self.bots[a].1.total_cmp(&self.bots[b].1))
+    }
+}
+
+/// `path` for `index` 0, `path.<index>` for the older checkpoints.
+pub fn checkpoint_path(path: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        return path.to_path_buf();
+    }
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Writes `data` to `path` without ever leaving a partial file behind: the
+/// data goes to `path.tmp` first, is synced and then renamed over `path`.
+/// The previous `keep - 1` versions stay as `path.1` (newest) to
+/// `path.<keep - 1>`.
+pub fn write_checkpoint(path: &Path, data: &[u8], keep: usize) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    if keep > 1 && path.exists() {
+        for i in (1..keep - 1).rev() {
+            let from = checkpoint_path(path, i);
+            if from.exists() {
+                fs::rename(from, checkpoint_path(path, i + 1))?;
+            }
+        }
+        // `path` stays in place until the rename below replaces it
+        let previous = checkpoint_path(path, 1);
+        let _ = fs::remove_file(&previous);
+        if fs::hard_link(path, &previous).is_err() {
+            fs::copy(path, &previous)?;
+        }
+    }
+    fs::rename(&tmp, path)?;
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        // persist the rename; not every platform can open a directory
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::prelude::*;
+
+    fn sample() -> Population {
+        let mut rng = thread_rng();
+        Population {
+            generation: 42,
+            metadata: vec![("mutation_rate".to_string(), "0.01".to_string())],
+            bots: (0..3)
+                .map(|i| (Network::new(&mut rng), i as f64 * 1.5))
+                .collect(),
+            extra: Sections::default(),
+        }
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let population = sample();
+        let data = population.encode();
+        let loaded = Population::decode(&data).unwrap();
+        assert_eq!(loaded.generation, 42);
+        assert_eq!(loaded.metadata, population.metadata);
+        assert!(loaded.extra.0.is_empty());
+        assert_eq!(loaded.bots.len(), 3);
+        for ((a, fa), (b, fb)) in population.bots.iter().zip(loaded.bots.iter()) {
+            assert_eq!(a.dump(), b.dump());
+            assert_eq!(fa, fb);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let data = sample().encode();
+        assert!(matches!(
+            Population::decode(&data[..data.len() - 1]),
+            Err(FormatError::Checksum { .. })
+        ));
+        assert!(matches!(
+            Population::decode(&[0u8; 64]),
+            Err(FormatError::BadMagic)
+        ));
+
+        let mut corrupt = data.clone();
+        corrupt[40] ^= 1;
+        assert!(matches!(
+            Population::decode(&corrupt),
+            Err(FormatError::Checksum { .. })
+        ));
+
+        let mut future = data[..data.len() - 4].to_vec();
+        future[8..12].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let crc = crc32(&future);
+        future.extend_from_slice(&crc.to_le_bytes());
+        assert!(matches!(
+            Population::decode(&future),
+            Err(FormatError::UnsupportedVersion(_))
+        ));
+
+        let mut sections = Sections::default();
+        let mut topology = Network::topology();
+        topology[1].inputs += 8;
+        sections.push(TAG_TOPOLOGY, encode_topology(&topology));
+        assert!(matches!(
+            Population::decode(&sections.encode()),
+            Err(FormatError::Topology { .. })
+        ));
+
+        // counts beyond the data are truncation, not allocations
+        let mut sections = Sections::default();
+        sections.push(TAG_TOPOLOGY, u32::MAX.to_le_bytes().to_vec());
+        assert!(matches!(
+            Population::decode(&sections.encode()),
+            Err(FormatError::Truncated)
+        ));
+        let mut sections = Sections::default();
+        sections.push(TAG_TOPOLOGY, encode_topology(&Network::topology()));
+        let mut w = Writer::new();
+        w.u64(0);
+        w.u32(u32::MAX);
+        sections.push(TAG_META, w.0);
+        assert!(matches!(
+            Population::decode(&sections.encode()),
+            Err(FormatError::Truncated)
+        ));
+        assert!(matches!(
+            Reader::new(&[0; 8]).f32s(usize::MAX),
+            Err(FormatError::Truncated)
+        ));
+        assert_eq!(Reader::new(&[0; 8]).f32s(2).unwrap(), [0.0; 2]);
+    }
+
+    #[test]
+    fn checkpoints() {
+        let dir = std::env::temp_dir().join(format!("2048ai-format-{}", std::process::id()));
+        let path = dir.join("population.bin");
+        for i in 0..5u8 {
+            write_checkpoint(&path, &[i], 3).unwrap();
+        }
+        assert_eq!(fs::read(&path).unwrap(), [4]);
+        assert_eq!(fs::read(checkpoint_path(&path, 1)).unwrap(), [3]);
+        assert_eq!(fs::read(checkpoint_path(&path, 2)).unwrap(), [2]);
+        assert!(!checkpoint_path(&path, 3).exists());
+        assert!(!dir.join("population.bin.tmp").exists());
+
+        write_checkpoint(&path, &[5], 1).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [5]);
+        assert_eq!(fs::read(checkpoint_path(&path, 1)).unwrap(), [3]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,159 +1,234 @@
-use crate::{engine::Board, nn::Network};
+use crate::diversity::{Behaviour, BEHAVIOUR};
+use crate::{
+    engine::{Board, Direction},
+    eval::Objective,
+    nn::Network,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Game {
     pub network: Network,
     pub score: usize,
+    pub points: usize,
+    /// Aggregated score over the seeds of the last `evaluate`.
+    pub fitness: f64,
+    /// Moves played over all seeds of the last `evaluate`.
+    pub moves: usize,
+    /// Mutation step size for `Mutation::SelfAdaptive`, zero until the bot
+    /// has been mutated.
+    pub step: f32,
+    /// Behavioural descriptor of the last `evaluate`, see
+    /// `diversity::Behaviour`.
+    pub behaviour: Behaviour,
+    /// Weight of the merge points along a search path against the network
+    /// value of its leaf, zero to search for survival only.
+    pub points_weight: f32,
 }
 
 impl Game {
     pub fn new(network: Network) -> Self {
-        Game { network, score: 0 }
+        Game {
+            network,
+            score: 0,
+            points: 0,
+            fitness: 0.0,
+            moves: 0,
+            step: 0.0,
+            behaviour: [0.0; BEHAVIOUR],
+            points_weight: 0.0,
+        }
     }
 
+    /// Plays greedily on the network value of the next board.
     #[allow(dead_code)]
-    pub fn run(&mut self) -> Board {
+    pub fn run(&mut self, seed: u64) -> Board {
         let mut result = 0;
-        let mut board = Board::new();
-        let mut next = None;
+        let mut points = 0;
+        let mut board = Board::new(seed);
         loop {
-            let moves = unsafe { board.moves() };
-            let mut max = -100_000f32;
-            if moves.up != board.data {
-                let b = board.spawn(moves.up, moves.free_ud);
-                let score = self.network.calc(&b.to_t8());
-                max = score;
-                next = Some(b);
-            }
-            if moves.down != board.data {
-                let b = board.spawn(moves.down, moves.free_ud);
-                let score = self.network.calc(&b.to_t8());
-                if score > max {
-                    max = score;
-                    next = Some(b);
-                }
-            }
-            if moves.left != board.data {
-                let b = board.spawn(moves.left, moves.free_rl);
+            let mut next: Option<(f32, Board, u32)> = None;
+            for (_, b, p) in board.successors() {
                 let score = self.network.calc(&b.to_t8());
-                if score > max {
-                    max = score;
-                    next = Some(b);
+                if next.is_none_or(|(max, _, _)| score > max) {
+                    next = Some((score, b, p));
                 }
             }
-            if moves.right != board.data {
-                let b = board.spawn(moves.right, moves.free_rl);
-                let score = self.network.calc(&b.to_t8());
-                if score > max {
-                    next = Some(b);
-                }
-            }
-            if let Some(b) = next {
-                board = b;
-                next = None;
-                result += 1;
-            } else {
+            let Some((_, b, p)) = next else {
                 break;
-            }
+            };
+            board = b;
+            result += 1;
+            points += p as usize;
         }
         self.score = result;
+        self.points = points;
         board
     }
 
     #[allow(dead_code)]
-    pub fn run_with_ai(&mut self, depth: usize) -> Board {
-        let mut board = Board::new();
+    pub fn run_with_ai(&mut self, depth: usize, seed: u64) -> Board {
+        self.play(depth, Board::new(seed), &mut [0; 4])
+    }
+
+    /// Plays from `board` like `run_with_ai`, counting the moves per
+    /// direction, indexed by `Direction as usize`.
+    fn play(&mut self, depth: usize, mut board: Board, directions: &mut [usize; 4]) -> Board {
         let mut result = 0;
-        loop {
-            let b = self.ai(depth, board);
-            if let Some(b) = b {
-                board = b;
-                result += 1;
-            } else {
-                break;
-            }
+        let mut points = 0;
+        while let Some((direction, b, p)) = self.decide(depth, board) {
+            board = b;
+            directions[direction as usize] += 1;
+            result += 1;
+            points += p as usize;
         }
         self.score = result;
+        self.points = points;
         board
     }
 
-    pub fn ai(&self, depth: usize, board: Board) -> Option<Board> {
-        let mut score = -100_000_001f32;
-        let moves = unsafe { board.moves() };
-        let mut res = None;
-        if board.data != moves.down {
-            let board = board.spawn(moves.down, moves.free_ud);
-            let s = self.node(depth - 1, board);
-            if score < s {
-                res = Some(board);
-                score = s;
-            }
+    /// Every board `ai` goes through from `board` to the end of the game,
+    /// `board` included.
+    pub fn trajectory(&self, depth: usize, mut board: Board) -> Vec<Board> {
+        let mut boards = vec![board];
+        while let Some((b, _)) = self.ai(depth, board) {
+            board = b;
+            boards.push(board);
         }
-        if board.data != moves.right {
-            let board = board.spawn(moves.right, moves.free_rl);
-            let s = self.node(depth - 1, board);
-            if score < s {
-                res = Some(board);
-                score = s;
-            }
+        boards
+    }
+
+    /// Plays every seed with `run_with_ai` as `objective` says and folds
+    /// what the games are worth into `fitness`. Also records the
+    /// `behaviour` of the games.
+    pub fn evaluate(&mut self, seeds: &[u64], objective: Objective) -> f64 {
+        let starts: Vec<Board> = seeds.iter().map(|s| Board::new(*s)).collect();
+        self.evaluate_from(&starts, objective)
+    }
+
+    /// `evaluate` with the games starting from `starts`, see `curriculum`.
+    pub fn evaluate_from(&mut self, starts: &[Board], objective: Objective) -> f64 {
+        self.points_weight = objective.points_weight;
+        let mut directions = [0; 4];
+        let mut behaviour = [0.0; BEHAVIOUR];
+        let mut moves = 0;
+        let scores: Vec<f64> = starts
+            .iter()
+            .map(|start| {
+                let board = self.play(objective.depth, *start, &mut directions);
+                let max = board.data.max_tile();
+                let cell = (0..16)
+                    .find(|i| (board.data.0 >> (i * 4)) & 0xf == max as u64)
+                    .unwrap();
+                behaviour[cell] += 1.0 / starts.len() as f32;
+                moves += self.score;
+                objective.fitness.value(self.score, self.points, &board)
+            })
+            .collect();
+        let total = directions.iter().sum::<usize>().max(1) as f32;
+        for (b, n) in behaviour[16..].iter_mut().zip(directions) {
+            *b = n as f32 / total;
         }
-        if board.data != moves.up {
-            let board = board.spawn(moves.up, moves.free_ud);
-            let s = self.node(depth - 1, board);
+        self.behaviour = behaviour;
+        self.fitness = objective.aggregate.apply(&scores);
+        self.moves = moves;
+        self.fitness
+    }
+
+    /// Picks the next board and returns it with the merge points of the move.
+    pub fn ai(&self, depth: usize, board: Board) -> Option<(Board, u32)> {
+        self.decide(depth, board)
+            .map(|(_, board, points)| (board, points))
+    }
+
+    /// `ai`, also returning the direction. Ties go to the first move in
+    /// `Direction::ALL`.
+    fn decide(&self, depth: usize, board: Board) -> Option<(Direction, Board, u32)> {
+        let mut score = -100_000_001f32;
+        let mut res = None;
+        for (dir, next, points) in board.successors() {
+            let s = self.node(depth - 1, next) + self.points_weight * points as f32;
             if score < s {
-                res = Some(board);
+                res = Some((dir, next, points));
                 score = s;
             }
         }
-        if board.data != moves.left {
-            let board = board.spawn(moves.left, moves.free_rl);
-            let s = self.node(depth - 1, board);
-            if score < s {
-                res = Some(board);
-            }
-        }
         res
     }
 
+    /// Value of `board`: the network value of the best leaf `depth` moves
+    /// ahead plus the weighted merge points on the way there.
     fn node(&self, depth: usize, board: Board) -> f32 {
         if depth == 0 {
             return self.network.calc(&board.to_t8());
         }
-        let mut score = -100_000f32;
-        let moves = unsafe { board.moves() };
-        if board.data != moves.down {
-            let board = board.spawn(moves.down, moves.free_ud);
-            score = score.max(self.node(depth - 1, board));
-        }
-        if board.data != moves.right {
-            let board = board.spawn(moves.right, moves.free_rl);
-            score = score.max(self.node(depth - 1, board));
-        }
-        if board.data != moves.up {
-            let board = board.spawn(moves.up, moves.free_ud);
-            score = score.max(self.node(depth - 1, board));
-        }
-        if board.data != moves.left {
-            let board = board.spawn(moves.left, moves.free_rl);
-            score = score.max(self.node(depth - 1, board));
-        }
-        score
+        board
+            .successors()
+            .map(|(_, next, points)| {
+                self.node(depth - 1, next) + self.points_weight * points as f32
+            })
+            .fold(-100_000f32, f32::max)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::engine::set_seed;
+    use crate::engine::Data;
+    use crate::eval::{Aggregate, Fitness};
     use rand::prelude::*;
     #[test]
     fn test() {
         let mut rng = thread_rng();
-        set_seed(123456);
         let network = Network::new(&mut rng);
         let mut game = Game::new(network);
-        let board = game.run();
+        let board = game.run(123456);
         println!("{}", board.data);
         println!("{}", game.score);
     }
+
+    #[test]
+    fn evaluate() {
+        let mut game = Game::new(Network::new(&mut thread_rng()));
+        let seeds = [1, 2, 3];
+        let scores: Vec<f64> = seeds
+            .iter()
+            .map(|seed| {
+                game.run_with_ai(1, *seed);
+                game.score as f64
+            })
+            .collect();
+        let objective = |aggregate, fitness| Objective {
+            depth: 1,
+            aggregate,
+            fitness,
+            points_weight: 0.0,
+        };
+        let min = game.evaluate(&seeds, objective(Aggregate::Min, Fitness::Moves));
+        assert_eq!(min, scores.iter().copied().fold(f64::MAX, f64::min));
+        let mean = game.evaluate(&seeds, objective(Aggregate::Mean, Fitness::Moves));
+        assert_eq!(mean, scores.iter().sum::<f64>() / 3.0);
+        assert_eq!(game.fitness, mean);
+        assert_eq!(game.moves as f64, mean * 3.0);
+        let b = game.behaviour;
+        assert!((b[..16].iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!((b[16..].iter().sum::<f32>() - 1.0).abs() < 1e-5);
+
+        let score = game.evaluate(&seeds[..1], objective(Aggregate::Mean, Fitness::Score));
+        assert_eq!(score, game.points as f64);
+        let tile = game.evaluate(&seeds[..1], objective(Aggregate::Mean, Fitness::MaxTile));
+        let end = game.run_with_ai(1, seeds[0]);
+        assert_eq!(tile, (1 << end.data.max_tile()) as f64);
+    }
+
+    #[test]
+    fn points_weight() {
+        // 2 2 in the top row: only left and right merge
+        let board = Board {
+            seed: 5,
+            data: Data(0x11),
+        };
+        let mut game = Game::new(Network::new(&mut thread_rng()));
+        game.points_weight = 1e6;
+        assert_eq!(game.ai(1, board).unwrap().1, 4);
+    }
 }
@@ -0,0 +1,393 @@
+//! Selection, mutation and crossover operators of the genetic algorithm.
+//!
+//! Operators work on the flat parameter vector of `Network::dump`. Every
+//! operator prints as the string it parses from (`tournament:3`,
+//! `gaussian:0.01:0.1`, `sbx:15`, ...), which is how it is recorded in the
+//! population metadata.
+
+use std::fmt;
+use std::str::FromStr;
+
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use rand_distr::StandardNormal;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Selection {
+    /// Fitness proportional, offset by 90% of the worst score.
+    Roulette,
+    /// Best of `k` bots drawn uniformly.
+    Tournament(usize),
+    /// Linear ranking: the i-th best of n is drawn with weight n - i.
+    Rank,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mutation {
+    /// Adds `U(-range, range)` to each weight with probability `rate`.
+    Uniform { rate: f32, range: f32 },
+    /// Adds `N(0, sigma)` to each weight with probability `rate`.
+    Gaussian { rate: f32, sigma: f32 },
+    /// Like `Gaussian`, but every bot carries its own sigma, which is itself
+    /// mutated log-normally before use. `sigma` is the initial value.
+    SelfAdaptive { rate: f32, sigma: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Crossover {
+    /// Swaps each weight with probability 1/2.
+    Uniform,
+    /// BLX-alpha: each child weight is drawn from the parents' interval
+    /// widened by `alpha` times its length on both sides.
+    Blend(f32),
+    /// Simulated binary crossover with distribution index `eta`.
+    Sbx(f32),
+}
+
+/// Splits `name:a:b` and parses the arguments.
+pub(crate) fn args<T: FromStr>(
+    s: &str,
+    name: &str,
+    count: usize,
+) -> Result<Option<Vec<T>>, String> {
+    let mut parts = s.split(':');
+    if parts.next() != Some(name) {
+        return Ok(None);
+    }
+    let args = parts
+        .map(|p| {
+            p.parse::<T>()
+                .map_err(|_| format!("invalid argument {:?} in {:?}", p, s))
+        })
+        .collect::<Result<Vec<T>, String>>()?;
+    if args.len() != count {
+        return Err(format!("{} takes {} argument(s), got {:?}", name, count, s));
+    }
+    Ok(Some(args))
+}
+
+impl FromStr for Selection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if args::<usize>(s, "roulette", 0)?.is_some() {
+            Ok(Selection::Roulette)
+        } else if args::<usize>(s, "rank", 0)?.is_some() {
+            Ok(Selection::Rank)
+        } else if let Some(a) = args::<usize>(s, "tournament", 1)? {
+            if a[0] == 0 {
+                return Err("tournament size must be at least 1".to_string());
+            }
+            Ok(Selection::Tournament(a[0]))
+        } else {
+            Err(format!(
+                "unknown selection {} (expected roulette, rank or tournament:<k>)",
+                s
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selection::Roulette => write!(f, "roulette"),
+            Selection::Tournament(k) => write!(f, "tournament:{}", k),
+            Selection::Rank => write!(f, "rank"),
+        }
+    }
+}
+
+impl FromStr for Mutation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if let Some(a) = args::<f32>(s, "uniform", 2)? {
+            Ok(Mutation::Uniform {
+                rate: a[0],
+                range: a[1],
+            })
+        } else if let Some(a) = args::<f32>(s, "gaussian", 2)? {
+            Ok(Mutation::Gaussian {
+                rate: a[0],
+                sigma: a[1],
+            })
+        } else if let Some(a) = args::<f32>(s, "self-adaptive", 2)? {
+            Ok(Mutation::SelfAdaptive {
+                rate: a[0],
+                sigma: a[1],
+            })
+        } else {
+            Err(format!(
+                "unknown mutation {} (expected uniform:<rate>:<range>, gaussian:<rate>:<sigma> or self-adaptive:<rate>:<sigma>)",
+                s
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mutation::Uniform { rate, range } => write!(f, "uniform:{}:{}", rate, range),
+            Mutation::Gaussian { rate, sigma } => write!(f, "gaussian:{}:{}", rate, sigma),
+            Mutation::SelfAdaptive { rate, sigma } => {
+                write!(f, "self-adaptive:{}:{}", rate, sigma)
+            }
+        }
+    }
+}
+
+impl FromStr for Crossover {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if args::<f32>(s, "uniform", 0)?.is_some() {
+            Ok(Crossover::Uniform)
+        } else if let Some(a) = args::<f32>(s, "blend", 1)? {
+            Ok(Crossover::Blend(a[0]))
+        } else if let Some(a) = args::<f32>(s, "sbx", 1)? {
+            Ok(Crossover::Sbx(a[0]))
+        } else {
+            Err(format!(
+                "unknown crossover {} (expected uniform, blend:<alpha> or sbx:<eta>)",
+                s
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Crossover {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Crossover::Uniform => write!(f, "uniform"),
+            Crossover::Blend(alpha) => write!(f, "blend:{}", alpha),
+            Crossover::Sbx(eta) => write!(f, "sbx:{}", eta),
+        }
+    }
+}
+
+/// Draws parent indices, favouring the high `scores`. The scores need not
+/// follow the order of the population: niching and novelty reorder it.
+pub enum Selector {
+    Weighted(WeightedIndex<f64>),
+    Tournament(usize, Vec<f64>),
+    Uniform(usize),
+}
+
+impl Selector {
+    pub fn new(selection: Selection, scores: &[f64]) -> Self {
+        let n = scores.len();
+        let weights: Vec<f64> = match selection {
+            Selection::Roulette => {
+                let min = scores.iter().copied().fold(f64::INFINITY, f64::min) * 0.9;
+                scores.iter().map(|s| (s - min).max(0.0)).collect()
+            }
+            Selection::Rank => {
+                // stable, so ties keep the population order
+                let mut order: Vec<usize> = (0..n).collect();
+                order.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]));
+                let mut weights = vec![0.0; n];
+                for (rank, i) in order.into_iter().enumerate() {
+                    weights[i] = (n - rank) as f64;
+                }
+                weights
+            }
+            Selection::Tournament(k) => return Selector::Tournament(k, scores.to_vec()),
+        };
+        match WeightedIndex::new(&weights) {
+            Ok(index) => Selector::Weighted(index),
+            // every bot scored zero
+            Err(_) => Selector::Uniform(n),
+        }
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        match self {
+            Selector::Weighted(index) => index.sample(rng),
+            Selector::Tournament(k, scores) => (0..*k)
+                .map(|_| rng.gen_range(0..scores.len()))
+                .reduce(|best, i| if scores[i] > scores[best] { i } else { best })
+                .unwrap(),
+            Selector::Uniform(n) => rng.gen_range(0..*n),
+        }
+    }
+}
+
+impl Mutation {
+    /// Mutates `genes` in place and returns the step size the child carries.
+    /// `step` is the parent's, zero when it has none yet.
+    pub fn apply<R: Rng>(&self, genes: &mut [f32], step: f32, rng: &mut R) -> f32 {
+        match *self {
+            Mutation::Uniform { rate, range } => {
+                for g in genes.iter_mut() {
+                    if rng.gen::<f32>() < rate {
+                        *g += rng.gen::<f32>() * range * 2.0 - range;
+                    }
+                }
+                step
+            }
+            Mutation::Gaussian { rate, sigma } => {
+                gaussian(genes, rate, sigma, rng);
+                step
+            }
+            Mutation::SelfAdaptive { rate, sigma } => {
+                let step = if step > 0.0 { step } else { sigma };
+                let tau = 1.0 / (genes.len() as f32).sqrt();
+                let z: f32 = rng.sample(StandardNormal);
+                let step = step * (tau * z).exp();
+                gaussian(genes, rate, step, rng);
+                step
+            }
+        }
+    }
+}
+
+fn gaussian<R: Rng>(genes: &mut [f32], rate: f32, sigma: f32, rng: &mut R) {
+    for g in genes.iter_mut() {
+        if rng.gen::<f32>() < rate {
+            let z: f32 = rng.sample(StandardNormal);
+            *g += sigma * z;
+        }
+    }
+}
+
+impl Crossover {
+    pub fn apply<R: Rng>(&self, a: &[f32], b: &[f32], rng: &mut R) -> (Vec<f32>, Vec<f32>) {
+        let mut c1 = a.to_vec();
+        let mut c2 = b.to_vec();
+        for i in 0..a.len() {
+            let (x, y) = (a[i], b[i]);
+            match *self {
+                Crossover::Uniform => {
+                    if rng.gen() {
+                        c1[i] = y;
+                        c2[i] = x;
+                    }
+                }
+                Crossover::Blend(alpha) => {
+                    let d = (x - y).abs() * alpha;
+                    let (lo, hi) = (x.min(y) - d, x.max(y) + d);
+                    c1[i] = lo + rng.gen::<f32>() * (hi - lo);
+                    c2[i] = lo + rng.gen::<f32>() * (hi - lo);
+                }
+                Crossover::Sbx(eta) => {
+                    if rng.gen() {
+                        continue;
+                    }
+                    let u: f32 = rng.gen();
+                    let beta = if u <= 0.5 {
+                        (2.0 * u).powf(1.0 / (eta + 1.0))
+                    } else {
+                        (1.0 / (2.0 * (1.0 - u))).powf(1.0 / (eta + 1.0))
+                    };
+                    c1[i] = 0.5 * ((1.0 + beta) * x + (1.0 - beta) * y);
+                    c2[i] = 0.5 * ((1.0 - beta) * x + (1.0 + beta) * y);
+                }
+            }
+        }
+        (c1, c2)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        for s in ["roulette", "rank", "tournament:3"] {
+            assert_eq!(s.parse::<Selection>().unwrap().to_string(), s);
+        }
+        for s in [
+            "uniform:0.01:0.2",
+            "gaussian:0.05:0.1",
+            "self-adaptive:1:0.05",
+        ] {
+            assert_eq!(s.parse::<Mutation>().unwrap().to_string(), s);
+        }
+        for s in ["uniform", "blend:0.5", "sbx:15"] {
+            assert_eq!(s.parse::<Crossover>().unwrap().to_string(), s);
+        }
+        assert!("tournament".parse::<Selection>().is_err());
+        assert!("tournament:0".parse::<Selection>().is_err());
+        assert!("gaussian:x:1".parse::<Mutation>().is_err());
+        assert!("one-point".parse::<Crossover>().is_err());
+    }
+
+    #[test]
+    fn select() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let scores = [100.0, 50.0, 40.0, 10.0];
+        for selection in [
+            Selection::Roulette,
+            Selection::Rank,
+            Selection::Tournament(2),
+        ] {
+            let selector = Selector::new(selection, &scores);
+            let mut counts = [0; 4];
+            for _ in 0..4000 {
+                counts[selector.sample(&mut rng)] += 1;
+            }
+            assert!(counts[0] > counts[3], "{} {:?}", selection, counts);
+        }
+        // the best bot need not come first
+        let scores = [10.0, 40.0, 100.0, 50.0];
+        for selection in [
+            Selection::Roulette,
+            Selection::Rank,
+            Selection::Tournament(2),
+        ] {
+            let selector = Selector::new(selection, &scores);
+            let mut counts = [0; 4];
+            for _ in 0..4000 {
+                counts[selector.sample(&mut rng)] += 1;
+            }
+            assert!(counts[2] > counts[3], "{} {:?}", selection, counts);
+            assert!(counts[3] > counts[0], "{} {:?}", selection, counts);
+        }
+        let selector = Selector::new(Selection::Roulette, &[0.0, 0.0]);
+        assert!(selector.sample(&mut rng) < 2);
+    }
+
+    #[test]
+    fn operators() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let a = vec![0f32; 64];
+        let b = vec![1f32; 64];
+        for crossover in [
+            Crossover::Uniform,
+            Crossover::Blend(0.0),
+            Crossover::Sbx(15.0),
+        ] {
+            let (c1, c2) = crossover.apply(&a, &b, &mut rng);
+            for i in 0..64 {
+                // the parents' mean is preserved by every operator but blend
+                if crossover != Crossover::Blend(0.0) {
+                    assert!((c1[i] + c2[i] - 1.0).abs() < 1e-5);
+                }
+                // sbx may extrapolate past its parents
+                if crossover != Crossover::Sbx(15.0) {
+                    assert!((0.0..=1.0).contains(&c1[i]), "{}", crossover);
+                }
+            }
+        }
+
+        let mut genes = vec![0f32; 64];
+        let step = Mutation::Gaussian {
+            rate: 1.0,
+            sigma: 0.1,
+        }
+        .apply(&mut genes, 0.0, &mut rng);
+        assert_eq!(step, 0.0);
+        assert!(genes.iter().all(|g| *g != 0.0));
+
+        let mutation = Mutation::SelfAdaptive {
+            rate: 0.0,
+            sigma: 0.1,
+        };
+        let step = mutation.apply(&mut genes, 0.0, &mut rng);
+        assert!(step > 0.0 && step != 0.1);
+        assert_ne!(mutation.apply(&mut genes, step, &mut rng), step);
+    }
+}
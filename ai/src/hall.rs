@@ -0,0 +1,246 @@
+//! Hall of fame: the best networks ever seen during training, ranked on a
+//! fixed set of validation seeds so that entries from different generations
+//! compare fairly. A change of seeds or objective rescores every entry.
+//!
+//! The archive is stored as a population file next to the population
+//! (`population.hof` for `population.bin`), so `ai eval`, `ai export` and
+//! `ai inspect` work on it directly.
+
+use std::path::{Path, PathBuf};
+
+use crate::eval::{par_map, parse_seeds, Objective};
+use crate::format::{FormatError, Population, Reader, Sections, Writer};
+use crate::game::Game;
+use crate::nn::Network;
+
+/// Generation each entry was found in.
+pub const TAG_GENERATIONS: [u8; 4] = *b"HOFG";
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub network: Network,
+    pub generation: usize,
+    /// Fitness over `HallOfFame::seeds` under `HallOfFame::objective`.
+    pub fitness: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct HallOfFame {
+    pub size: usize,
+    pub seeds: Vec<u64>,
+    /// What the entries were validated with, `None` until the first
+    /// validation and for archives written before it was recorded.
+    pub objective: Option<Objective>,
+    /// Best first.
+    pub entries: Vec<Entry>,
+}
+
+pub fn hall_path(population: &Path) -> PathBuf {
+    population.with_extension("hof")
+}
+
+impl HallOfFame {
+    pub fn new(size: usize, seeds: Vec<u64>) -> Self {
+        HallOfFame {
+            size,
+            seeds,
+            objective: None,
+            entries: vec![],
+        }
+    }
+
+    pub fn contains(&self, network: &Network) -> bool {
+        let dump = network.dump();
+        self.entries.iter().any(|e| e.network.dump() == dump)
+    }
+
+    /// Adds `entry` unless the network is already archived. Returns whether
+    /// it made the cut.
+    pub fn insert(&mut self, entry: Entry) -> bool {
+        if self.size == 0 || self.contains(&entry.network) {
+            return false;
+        }
+        let at = self
+            .entries
+            .iter()
+            .position(|e| e.fitness < entry.fitness)
+            .unwrap_or(self.entries.len());
+        if at >= self.size {
+            return false;
+        }
+        self.entries.insert(at, entry);
+        self.entries.truncate(self.size);
+        true
+    }
+
+    pub fn best(&self) -> Option<&Entry> {
+        self.entries.first()
+    }
+
+    /// Scores `network` on the validation seeds.
+    pub fn validate(&self, network: &Network, objective: Objective, workers: usize) -> f64 {
+        let scores = par_map(&self.seeds, workers, |seed| {
+            let mut game = Game::new(*network);
+            game.evaluate(&[*seed], objective)
+        });
+        objective.aggregate.apply(&scores)
+    }
+
+    /// Switches to new validation seeds or objective and re-ranks every entry
+    /// on them.
+    pub fn revalidate(&mut self, seeds: Vec<u64>, objective: Objective, workers: usize) {
+        self.seeds = seeds;
+        self.objective = Some(objective);
+        let mut entries = std::mem::take(&mut self.entries);
+        for entry in entries.iter_mut() {
+            entry.fitness = self.validate(&entry.network, objective, workers);
+        }
+        entries.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+        self.entries = entries;
+    }
+
+    pub fn to_population(&self) -> Population {
+        let seeds = self
+            .seeds
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut w = Writer::new();
+        self.entries.iter().for_each(|e| w.u64(e.generation as u64));
+        let mut extra = Sections::default();
+        extra.push(TAG_GENERATIONS, w.0);
+        let mut metadata = vec![
+            ("hall_of_fame".to_string(), self.size.to_string()),
+            ("validation_seeds".to_string(), seeds),
+        ];
+        if let Some(objective) = self.objective {
+            metadata.extend([
+                ("depth".to_string(), objective.depth.to_string()),
+                ("aggregate".to_string(), objective.aggregate.to_string()),
+                ("fitness".to_string(), objective.fitness.to_string()),
+                (
+                    "points_weight".to_string(),
+                    objective.points_weight.to_string(),
+                ),
+            ]);
+        }
+        Population {
+            generation: self.entries.iter().map(|e| e.generation).max().unwrap_or(0) as u64,
+            metadata,
+            bots: self
+                .entries
+                .iter()
+                .map(|e| (e.network, e.fitness))
+                .collect(),
+            extra,
+        }
+    }
+
+    pub fn from_population(population: &Population, size: usize) -> Result<Self, FormatError> {
+        let seeds = population
+            .metadata
+            .iter()
+            .find(|(k, _)| k == "validation_seeds")
+            .ok_or(FormatError::Invalid(
+                "hall of fame without validation seeds",
+            ))?;
+        let seeds =
+            parse_seeds(&seeds.1).map_err(|_| FormatError::Invalid("bad validation seeds"))?;
+        // unrecorded or unreadable, the next update revalidates
+        let objective = read_objective(&population.metadata);
+        let mut r = Reader::new(population.extra.require(TAG_GENERATIONS)?);
+        let mut entries = Vec::with_capacity(population.bots.len());
+        for (network, fitness) in population.bots.iter() {
+            entries.push(Entry {
+                network: *network,
+                generation: r.u64()? as usize,
+                fitness: *fitness,
+            });
+        }
+        entries.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+        entries.truncate(size);
+        Ok(HallOfFame {
+            size,
+            seeds,
+            objective,
+            entries,
+        })
+    }
+}
+
+fn read_objective(metadata: &[(String, String)]) -> Option<Objective> {
+    let get = |key: &str| {
+        metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    };
+    Some(Objective {
+        depth: get("depth")?.parse().ok()?,
+        aggregate: get("aggregate")?.parse().ok()?,
+        fitness: get("fitness")?.parse().ok()?,
+        points_weight: get("points_weight")?.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::{Aggregate, Fitness};
+    use rand::prelude::*;
+
+    fn entry(fitness: f64) -> Entry {
+        Entry {
+            network: Network::new(&mut thread_rng()),
+            generation: fitness as usize,
+            fitness,
+        }
+    }
+
+    #[test]
+    fn insert() {
+        let mut hall = HallOfFame::new(3, vec![1, 2]);
+        for f in [5.0, 1.0, 3.0] {
+            assert!(hall.insert(entry(f)));
+        }
+        assert!(!hall.insert(entry(0.5)));
+        let best = entry(9.0);
+        assert!(hall.insert(best));
+        assert!(!hall.insert(best));
+        let fitness: Vec<f64> = hall.entries.iter().map(|e| e.fitness).collect();
+        assert_eq!(fitness, [9.0, 5.0, 3.0]);
+
+        let population = Population::decode(&hall.to_population().encode()).unwrap();
+        assert_eq!(population.best(), Some(0));
+        let loaded = HallOfFame::from_population(&population, 2).unwrap();
+        assert_eq!(loaded.seeds, [1, 2]);
+        assert_eq!(loaded.objective, None);
+        assert_eq!(loaded.entries.len(), 2);
+        assert_eq!(loaded.entries[0].generation, 9);
+        assert_eq!(loaded.entries[0].network.dump(), best.network.dump());
+    }
+
+    #[test]
+    fn revalidate() {
+        let mut hall = HallOfFame::new(2, vec![]);
+        hall.insert(entry(100.0));
+        hall.insert(entry(50.0));
+        let objective = Objective {
+            depth: 1,
+            aggregate: Aggregate::Mean,
+            fitness: Fitness::Moves,
+            points_weight: 0.0,
+        };
+        hall.revalidate(vec![3, 4], objective, 2);
+        assert_eq!(hall.seeds, [3, 4]);
+        assert_eq!(hall.objective, Some(objective));
+        let population = Population::decode(&hall.to_population().encode()).unwrap();
+        let loaded = HallOfFame::from_population(&population, 2).unwrap();
+        assert_eq!(loaded.objective, Some(objective));
+        for e in hall.entries.iter() {
+            assert_eq!(e.fitness, hall.validate(&e.network, objective, 1));
+        }
+        assert!(hall.entries[0].fitness >= hall.entries[1].fitness);
+    }
+}
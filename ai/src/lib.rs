@@ -0,0 +1,20 @@
+pub mod cli;
+pub mod cmaes;
+pub mod configs;
+pub mod curriculum;
+pub mod dist;
+pub mod diversity;
+pub mod engine;
+pub mod eval;
+pub mod format;
+pub mod game;
+pub mod genetic;
+pub mod hall;
+pub mod metrics;
+pub mod nn;
+pub mod npz;
+pub mod pack;
+pub mod progress;
+pub mod render;
+pub mod shutdown;
+pub mod world;
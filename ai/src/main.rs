@@ -1,16 +1,27 @@
-mod configs;
-mod engine;
-mod game;
-mod nn;
-mod world;
-mod progress;
-
-use world::World;
+use ai::cli;
 
 fn main() {
-    let mut world = World::new();
-    loop {
-        world.run(8);
-        world.update();
+    let mut args = std::env::args().skip(1);
+    let command = args.next().unwrap_or_default();
+    let args: Vec<String> = args.collect();
+    let result = match command.as_str() {
+        "train" => cli::train(args),
+        "worker" => cli::worker(args),
+        "eval" => cli::eval(args),
+        "play" => cli::play(args),
+        "export" => cli::export(args),
+        "pack" => cli::pack(args),
+        "inspect" => cli::inspect(args),
+        "-h" | "--help" | "help" => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        "" => Err("no command given".to_string()),
+        _ => Err(format!("unknown command {}", command)),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        eprintln!("{}", cli::USAGE);
+        std::process::exit(2);
     }
 }
@@ -0,0 +1,298 @@
+//! One record per training generation, appended to a CSV or JSON Lines file
+//! for plotting learning curves and comparing runs.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::diversity::mean_distance;
+use crate::eval::Summary;
+use crate::game::Game;
+
+/// Every operator `World::operators` reports.
+pub const OPERATORS: [&str; 6] = ["random", "sample", "mutation", "crossover", "copy", "elite"];
+
+/// Bots `Record::distance` is measured on.
+pub const DISTANCE_SAMPLE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub generation: usize,
+    pub best: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub worst: f64,
+    /// See `diversity`.
+    pub diversity: f64,
+    /// Mean genome distance, see `diversity::mean_distance`.
+    pub distance: f64,
+    /// Species found by `Niching::Species`, zero with other niching.
+    pub species: usize,
+    /// Mean novelty of the bots, zero without novelty search.
+    pub novelty: f64,
+    /// Evaluation time of this generation.
+    pub elapsed: Duration,
+    /// Time since training started.
+    pub total: Duration,
+    pub games: usize,
+    pub moves: usize,
+    /// How the evaluated bots were produced, `(operator, count)`.
+    pub operators: Vec<(String, usize)>,
+}
+
+impl Record {
+    pub fn new(
+        generation: usize,
+        bots: &[Game],
+        games: usize,
+        elapsed: Duration,
+        total: Duration,
+    ) -> Self {
+        let fitness = Summary::new(bots.iter().map(|b| b.fitness));
+        let genomes: Vec<Vec<f32>> = bots.iter().map(|b| b.network.dump()).collect();
+        Record {
+            generation,
+            best: fitness.max,
+            mean: fitness.mean,
+            median: fitness.median,
+            worst: fitness.min,
+            diversity: diversity(bots),
+            distance: mean_distance(&genomes, DISTANCE_SAMPLE),
+            species: 0,
+            novelty: 0.0,
+            elapsed,
+            total,
+            games,
+            moves: bots.iter().map(|b| b.moves).sum(),
+            operators: vec![],
+        }
+    }
+
+    pub fn games_per_sec(&self) -> f64 {
+        self.games as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    pub fn moves_per_sec(&self) -> f64 {
+        self.moves as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    fn fields(&self) -> Vec<(String, String)> {
+        let mut fields = vec![
+            ("generation", self.generation.to_string()),
+            ("best", self.best.to_string()),
+            ("mean", self.mean.to_string()),
+            ("median", self.median.to_string()),
+            ("worst", self.worst.to_string()),
+            ("diversity", self.diversity.to_string()),
+            ("distance", self.distance.to_string()),
+            ("species", self.species.to_string()),
+            ("novelty", self.novelty.to_string()),
+            ("elapsed", self.elapsed.as_secs_f64().to_string()),
+            ("total", self.total.as_secs_f64().to_string()),
+            ("games", self.games.to_string()),
+            ("moves", self.moves.to_string()),
+            ("games_per_sec", self.games_per_sec().to_string()),
+            ("moves_per_sec", self.moves_per_sec().to_string()),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect::<Vec<_>>();
+        // fixed columns so that every CSV row lines up with the header
+        fields.extend(OPERATORS.iter().map(|op| {
+            let n = self
+                .operators
+                .iter()
+                .find(|(k, _)| k == op)
+                .map_or(0, |(_, n)| *n);
+            (format!("op_{}", op), n.to_string())
+        }));
+        fields
+    }
+
+    pub fn to_json(&self) -> String {
+        let numbers = self
+            .fields()
+            .into_iter()
+            .filter(|(k, _)| !k.starts_with("op_"))
+            .map(|(k, v)| format!("\"{}\":{}", k, json_number(&v)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let operators = self
+            .operators
+            .iter()
+            .map(|(op, n)| format!("\"{}\":{}", op, n))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("{{{},\"operators\":{{{}}}}}", numbers, operators)
+    }
+}
+
+/// JSON has no NaN or infinity.
+fn json_number(v: &str) -> &str {
+    match v {
+        "NaN" | "inf" | "-inf" => "null",
+        v => v,
+    }
+}
+
+/// Mean over the parameters of their standard deviation across the
+/// population: zero once every bot carries the same network.
+pub fn diversity(bots: &[Game]) -> f64 {
+    if bots.len() < 2 {
+        return 0.0;
+    }
+    let mut sum = vec![0f64; crate::nn::Network::size()];
+    let mut sq = vec![0f64; sum.len()];
+    for bot in bots {
+        for (i, w) in bot.network.dump().into_iter().enumerate() {
+            sum[i] += w as f64;
+            sq[i] += (w as f64) * (w as f64);
+        }
+    }
+    let n = bots.len() as f64;
+    sum.iter()
+        .zip(sq.iter())
+        .map(|(s, q)| (q / n - (s / n).powi(2)).max(0.0).sqrt())
+        .sum::<f64>()
+        / sum.len() as f64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Jsonl,
+}
+
+/// Appends records to a file; the format follows the extension, `.csv` or
+/// anything else for JSON Lines.
+pub struct Sink {
+    out: BufWriter<File>,
+    format: Format,
+    /// CSV columns, taken from the first record of a new file.
+    header: Option<Vec<String>>,
+}
+
+impl Sink {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let format = match path.extension() {
+            Some(e) if e == "csv" => Format::Csv,
+            _ => Format::Jsonl,
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        // a resumed run keeps appending under the existing header
+        let header = match (format, file.metadata()?.len()) {
+            (Format::Csv, len) if len > 0 => {
+                let text = std::fs::read_to_string(path)?;
+                text.lines()
+                    .next()
+                    .map(|l| l.split(',').map(String::from).collect())
+            }
+            _ => None,
+        };
+        Ok(Sink {
+            out: BufWriter::new(file),
+            format,
+            header,
+        })
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        match self.format {
+            Format::Jsonl => writeln!(self.out, "{}", record.to_json())?,
+            Format::Csv => {
+                let fields = record.fields();
+                if self.header.is_none() {
+                    let header: Vec<String> = fields.iter().map(|(k, _)| k.clone()).collect();
+                    writeln!(self.out, "{}", header.join(","))?;
+                    self.header = Some(header);
+                }
+                let header = self.header.as_ref().unwrap();
+                let row = header
+                    .iter()
+                    .map(|h| {
+                        fields
+                            .iter()
+                            .find(|(k, _)| k == h)
+                            .map_or(String::new(), |(_, v)| v.clone())
+                    })
+                    .collect::<Vec<_>>();
+                writeln!(self.out, "{}", row.join(","))?;
+            }
+        }
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nn::Network;
+    use rand::prelude::*;
+
+    fn sample(generation: usize) -> Record {
+        let mut bots = vec![Game::new(Network::new(&mut thread_rng())); 3];
+        for (i, bot) in bots.iter_mut().enumerate() {
+            bot.fitness = i as f64;
+            bot.moves = 10;
+        }
+        let mut record = Record::new(
+            generation,
+            &bots,
+            3,
+            Duration::from_secs(2),
+            Duration::from_secs(4),
+        );
+        record.operators = vec![("mutation".to_string(), 2), ("copy".to_string(), 1)];
+        record
+    }
+
+    #[test]
+    fn record() {
+        let r = sample(5);
+        assert_eq!((r.best, r.median, r.worst, r.mean), (2.0, 1.0, 0.0, 1.0));
+        // identical networks
+        assert_eq!(r.diversity, 0.0);
+        assert_eq!(r.moves_per_sec(), 15.0);
+        assert_eq!(
+            r.to_json(),
+            "{\"generation\":5,\"best\":2,\"mean\":1,\"median\":1,\"worst\":0,\"diversity\":0,\
+             \"distance\":0,\"species\":0,\"novelty\":0,\"elapsed\":2,\"total\":4,\"games\":3,\"moves\":30,\"games_per_sec\":1.5,\
+             \"moves_per_sec\":15,\"operators\":{\"mutation\":2,\"copy\":1}}"
+        );
+
+        let mut rng = thread_rng();
+        let bots: Vec<Game> = (0..4).map(|_| Game::new(Network::new(&mut rng))).collect();
+        assert!(diversity(&bots) > 0.0);
+    }
+
+    #[test]
+    fn sink() {
+        let dir = std::env::temp_dir().join(format!("2048ai-metrics-{}", std::process::id()));
+        let csv = dir.join("metrics.csv");
+        for generation in 0..2 {
+            // reopened like a resumed run
+            Sink::open(&csv)
+                .unwrap()
+                .write(&sample(generation))
+                .unwrap();
+        }
+        let text = std::fs::read_to_string(&csv).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("generation,best,mean"));
+        assert!(lines[0].ends_with("op_random,op_sample,op_mutation,op_crossover,op_copy,op_elite"));
+        assert!(lines[1].ends_with(",0,0,2,0,1,0"));
+        assert!(lines[2].starts_with("1,2,1,1,0,"));
+
+        let jsonl = dir.join("metrics.jsonl");
+        let mut sink = Sink::open(&jsonl).unwrap();
+        sink.write(&sample(0)).unwrap();
+        sink.write(&sample(1)).unwrap();
+        assert_eq!(std::fs::read_to_string(&jsonl).unwrap().lines().count(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -3,25 +3,25 @@ use std::arch::x86_64::*;
 use std::mem::transmute;
 use std::ops;
 
-use crate::configs::{MUTATION_RANGE, MUTATION_RATE};
-
 const ZERO: __m256 = unsafe { transmute([0f32; 8]) };
 
 #[derive(Debug, Clone, Copy)]
 pub struct T8(__m256);
 
 impl T8 {
+    #[allow(clippy::missing_transmute_annotations)]
     pub const fn new(v: [f32; 8]) -> Self {
         T8(unsafe { transmute(v) })
     }
 
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::missing_safety_doc)]
     #[target_feature(enable = "avx2")]
     pub unsafe fn init(v: f32) -> Self {
         T8(_mm256_set1_ps(v))
     }
 
-    pub fn random(rng: &mut ThreadRng, min: &Self, max: &Self) -> Self {
+    #[allow(clippy::needless_range_loop)]
+    pub fn random<R: Rng>(rng: &mut R, min: &Self, max: &Self) -> Self {
         let mut v = [0f32; 8];
         for i in 0..8 {
             v[i] = rng.gen();
@@ -32,29 +32,6 @@ impl T8 {
         res
     }
 
-    pub fn mutate(&self, rng: &mut ThreadRng) -> Self {
-        let mut v: [f32; 8] = unsafe { transmute(self.0) };
-        for i in 0..8 {
-            if rng.gen::<f32>() < MUTATION_RATE {
-                v[i] += rng.gen::<f32>() * MUTATION_RANGE * 2.0 - MUTATION_RANGE;
-            }
-        }
-        T8::new(v)
-    }
-
-    pub fn cross(&self, other: &Self, rng: &mut ThreadRng) -> (Self, Self) {
-        let mut v1: [f32; 8] = unsafe { transmute(self.0) };
-        let mut v2: [f32; 8] = unsafe { transmute(other.0) };
-        for i in 0..8 {
-            if rng.gen() {
-                let tmp = v1[i];
-                v1[i] = v2[i];
-                v2[i] = tmp;
-            }
-        }
-        (T8::new(v1), T8::new(v2))
-    }
-
     #[target_feature(enable = "avx2")]
     unsafe fn sum(&self) -> f32 {
         let low = _mm256_castps256_ps128(self.0);
@@ -71,6 +48,7 @@ impl T8 {
     }
 
     #[target_feature(enable = "avx2")]
+    #[allow(unused_unsafe)]
     unsafe fn _add(&self, other: &Self) -> Self {
         T8(unsafe { _mm256_add_ps(self.0, other.0) })
     }
@@ -81,6 +59,7 @@ impl T8 {
     }
 
     #[target_feature(enable = "avx2")]
+    #[allow(unused_unsafe)]
     unsafe fn _sub(&self, other: &Self) -> Self {
         T8(unsafe { _mm256_sub_ps(self.0, other.0) })
     }
@@ -91,6 +70,7 @@ impl T8 {
     }
 
     #[target_feature(enable = "avx2")]
+    #[allow(unused_unsafe)]
     unsafe fn _mul(&self, other: &Self) -> Self {
         T8(unsafe { _mm256_mul_ps(self.0, other.0) })
     }
@@ -156,6 +136,40 @@ impl ops::MulAssign for T8 {
 const ONE: T8 = T8::new([1f32; 8]);
 const MINUS_ONE: T8 = T8::new([-1f32; 8]);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+    Dense,
+    Output,
+}
+
+impl LayerKind {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            LayerKind::Dense => 0,
+            LayerKind::Output => 1,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(LayerKind::Dense),
+            1 => Some(LayerKind::Output),
+            _ => None,
+        }
+    }
+}
+
+/// Shape of one layer as it appears in `Network::dump`: a dense layer dumps
+/// `outputs * inputs` weights followed by `outputs` biases, the output layer
+/// dumps `inputs` weights only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerShape {
+    pub name: &'static str,
+    pub kind: LayerKind,
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
 macro_rules! layer {
     ($name:ident, $in_size:expr, $out_size:expr) => {
         #[derive(Debug, Clone, Copy)]
@@ -165,7 +179,7 @@ macro_rules! layer {
         }
 
         impl $name {
-            pub fn new(rng: &mut ThreadRng) -> Self {
+            pub fn new<R: Rng>(rng: &mut R) -> Self {
                 let mut weights = [[T8::new([0f32; 8]); $in_size]; $out_size * 8];
                 let mut biases = [T8::new([0f32; 8]); $out_size];
                 for i in 0..$out_size {
@@ -200,6 +214,15 @@ macro_rules! layer {
                 $in_size * $out_size * 8 * 8 + $out_size * 8
             }
 
+            pub const fn shape(name: &'static str) -> LayerShape {
+                LayerShape {
+                    name,
+                    kind: LayerKind::Dense,
+                    inputs: $in_size * 8,
+                    outputs: $out_size * 8,
+                }
+            }
+
             pub fn dump(&self) -> Vec<f32> {
                 let mut res = Vec::with_capacity(Self::size());
                 for ws in self.weights.iter() {
@@ -237,44 +260,6 @@ macro_rules! layer {
                 }
                 $name { weights, biases }
             }
-
-            pub fn mutate(&mut self, rng: &mut ThreadRng) {
-                for ws in self.weights.iter_mut() {
-                    for w in ws.iter_mut() {
-                        *w = w.mutate(rng);
-                    }
-                }
-                for b in self.biases.iter_mut() {
-                    *b = b.mutate(rng);
-                }
-            }
-
-            pub fn cross(&self, other: &Self, rng: &mut ThreadRng) -> (Self, Self) {
-                let mut weights = [[T8::new([0f32; 8]); $in_size]; $out_size * 8];
-                let mut biases = [T8::new([0f32; 8]); $out_size];
-                let mut weights2 = [[T8::new([0f32; 8]); $in_size]; $out_size * 8];
-                let mut biases2 = [T8::new([0f32; 8]); $out_size];
-                for i in 0..$out_size {
-                    for j in 0..8 {
-                        for k in 0..8 {
-                            let (w1, w2) =
-                                self.weights[i * 8 + j][k].cross(&other.weights[i * 8 + j][k], rng);
-                            weights[i * 8 + j][k] = w1;
-                            weights2[i * 8 + j][k] = w2;
-                        }
-                    }
-                    let (b1, b2) = self.biases[i].cross(&other.biases[i], rng);
-                    biases[i] = b1;
-                    biases2[i] = b2;
-                }
-                (
-                    $name { weights, biases },
-                    $name {
-                        weights: weights2,
-                        biases: biases2,
-                    },
-                )
-            }
         }
     };
 }
@@ -287,7 +272,7 @@ macro_rules! output_layer {
         }
 
         impl $name {
-            pub fn new(rng: &mut ThreadRng) -> Self {
+            pub fn new<R: Rng>(rng: &mut R) -> Self {
                 let mut weights = [T8::new([0f32; 8]); $in_size];
                 for i in 0..$in_size {
                     weights[i] = T8::random(rng, &MINUS_ONE, &ONE);
@@ -307,6 +292,15 @@ macro_rules! output_layer {
                 $in_size * 8
             }
 
+            pub const fn shape(name: &'static str) -> LayerShape {
+                LayerShape {
+                    name,
+                    kind: LayerKind::Output,
+                    inputs: $in_size * 8,
+                    outputs: 1,
+                }
+            }
+
             pub fn dump(&self) -> Vec<f32> {
                 let mut res = Vec::with_capacity(Self::size());
                 for i in 0..$in_size {
@@ -328,23 +322,6 @@ macro_rules! output_layer {
                 }
                 $name { weights }
             }
-
-            pub fn mutate(&mut self, rng: &mut ThreadRng) {
-                for i in 0..$in_size {
-                    self.weights[i] = self.weights[i].mutate(rng);
-                }
-            }
-
-            pub fn cross(&self, other: &Self, rng: &mut ThreadRng) -> (Self, Self) {
-                let mut weights = [T8::new([0f32; 8]); $in_size];
-                let mut weights2 = [T8::new([0f32; 8]); $in_size];
-                for i in 0..$in_size {
-                    let (w1, w2) = self.weights[i].cross(&other.weights[i], rng);
-                    weights[i] = w1;
-                    weights2[i] = w2;
-                }
-                ($name { weights }, $name { weights: weights2 })
-            }
         }
     };
 }
@@ -358,7 +335,7 @@ macro_rules! network {
         }
 
         impl $name {
-            pub fn new(rng: &mut ThreadRng) -> Self {
+            pub fn new<R: Rng>(rng: &mut R) -> Self {
                 $name {
                     output_layer: <$out>::new(rng),
                     $($names: <$layer>::new(rng)),+
@@ -374,6 +351,14 @@ macro_rules! network {
                 <$out>::size() $(+ <$layer>::size())+
             }
 
+            /// Layers in `dump` order.
+            pub fn topology() -> Vec<LayerShape> {
+                vec![
+                    <$out>::shape("output_layer"),
+                    $(<$layer>::shape(stringify!($names))),+
+                ]
+            }
+
             pub fn dump(&self) -> Vec<f32> {
                 let mut res = Vec::with_capacity(Self::size());
                 res.extend_from_slice(&self.output_layer.dump());
@@ -396,25 +381,6 @@ macro_rules! network {
                 }
             }
 
-            pub fn mutate(&mut self, rng: &mut ThreadRng) {
-                $(self.$names.mutate(rng);)+
-                self.output_layer.mutate(rng);
-            }
-
-            pub fn cross(&self, other: &Self, rng: &mut ThreadRng) -> (Self, Self) {
-                let output_layer = self.output_layer.cross(&other.output_layer, rng);
-                $(let $names = self.$names.cross(&other.$names, rng);)+
-                (
-                    $name {
-                        output_layer: output_layer.0,
-                        $($names: $names.0),+
-                    },
-                    $name {
-                        output_layer: output_layer.1,
-                        $($names: $names.1),+
-                    }
-                )
-            }
         }
     };
 }
@@ -0,0 +1,528 @@
+//! NumPy `.npy` / `.npz` export and import.
+//!
+//! Every layer of the `network!` topology becomes two arrays, `<name>.weights`
+//! with shape `(outputs, inputs)` and `<name>.biases` with shape `(outputs,)`.
+//! The output layer only has `output_layer.weights` with shape `(inputs,)`.
+//! A population adds a leading `(bots,)` axis to every array plus `fitness`
+//! and `generation`, so `np.load("pop.npz")["a.weights"][0]` is the first
+//! bot's first layer.
+//!
+//! Archives are written uncompressed, exactly like `np.savez`.
+
+use crate::format::{crc32, FormatError, Population, Reader, Sections, Writer};
+use crate::nn::{LayerKind, LayerShape, Network};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NpyData {
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+    I64(Vec<i64>),
+}
+
+impl NpyData {
+    fn descr(&self) -> &'static str {
+        match self {
+            NpyData::F32(_) => "<f4",
+            NpyData::F64(_) => "<f8",
+            NpyData::I64(_) => "<i8",
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            NpyData::F32(v) => v.len(),
+            NpyData::F64(v) => v.len(),
+            NpyData::I64(v) => v.len(),
+        }
+    }
+
+    pub fn to_f32(&self) -> Vec<f32> {
+        match self {
+            NpyData::F32(v) => v.clone(),
+            NpyData::F64(v) => v.iter().map(|x| *x as f32).collect(),
+            NpyData::I64(v) => v.iter().map(|x| *x as f32).collect(),
+        }
+    }
+
+    pub fn to_f64(&self) -> Vec<f64> {
+        match self {
+            NpyData::F32(v) => v.iter().map(|x| *x as f64).collect(),
+            NpyData::F64(v) => v.clone(),
+            NpyData::I64(v) => v.iter().map(|x| *x as f64).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NpyArray {
+    pub shape: Vec<usize>,
+    pub data: NpyData,
+}
+
+impl NpyArray {
+    pub fn new(shape: Vec<usize>, data: NpyData) -> Self {
+        assert_eq!(shape.iter().product::<usize>(), data.len());
+        Self { shape, data }
+    }
+
+    /// Serialises the array as a version 1.0 `.npy` file.
+    pub fn to_npy(&self) -> Vec<u8> {
+        let mut shape = String::new();
+        for s in self.shape.iter() {
+            shape += &format!("{}, ", s);
+        }
+        if self.shape.len() > 1 {
+            shape.truncate(shape.len() - 2);
+        } else {
+            shape.truncate(shape.len().saturating_sub(1));
+        }
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': ({}), }}",
+            self.data.descr(),
+            shape
+        );
+        // magic + version + header length + header + '\n' is padded to 64 bytes
+        let total = 10 + header.len() + 1;
+        header += &" ".repeat((64 - total % 64) % 64);
+        header.push('\n');
+
+        let mut w = Writer::new();
+        w.0.extend_from_slice(b"\x93NUMPY\x01\x00");
+        w.u16(header.len() as u16);
+        w.0.extend_from_slice(header.as_bytes());
+        match &self.data {
+            NpyData::F32(v) => w.f32s(v),
+            NpyData::F64(v) => v.iter().for_each(|x| w.f64(*x)),
+            NpyData::I64(v) => v.iter().for_each(|x| w.u64(*x as u64)),
+        }
+        w.0
+    }
+
+    pub fn from_npy(data: &[u8]) -> Result<Self, FormatError> {
+        let mut r = Reader::new(data);
+        if r.bytes(6)? != b"\x93NUMPY" {
+            return Err(FormatError::Npz("not a .npy file".to_string()));
+        }
+        let major = r.u8()?;
+        let _minor = r.u8()?;
+        let header_len = match major {
+            1 => u16::from_le_bytes([r.u8()?, r.u8()?]) as usize,
+            2 | 3 => r.u32()? as usize,
+            _ => {
+                return Err(FormatError::Npz(format!(
+                    "unsupported .npy version {}",
+                    major
+                )))
+            }
+        };
+        let header = std::str::from_utf8(r.bytes(header_len)?)
+            .map_err(|_| FormatError::Npz("header is not utf-8".to_string()))?;
+
+        let descr = header_value(header, "descr")?;
+        let descr = descr.trim_matches(|c| c == '\'' || c == '"');
+        if header_value(header, "fortran_order")? != "False" {
+            return Err(FormatError::Npz(
+                "fortran order arrays are not supported".to_string(),
+            ));
+        }
+        let shape_str = header_value(header, "shape")?;
+        let mut shape = Vec::new();
+        for s in shape_str
+            .trim_start_matches('(')
+            .trim_end_matches(')')
+            .split(',')
+        {
+            let s = s.trim();
+            if !s.is_empty() {
+                shape.push(
+                    s.parse::<usize>()
+                        .map_err(|_| FormatError::Npz(format!("bad shape {}", shape_str)))?,
+                );
+            }
+        }
+        let len = shape.iter().product::<usize>();
+        let data = match descr {
+            "<f4" => NpyData::F32(r.f32s(len)?),
+            "<f8" => NpyData::F64((0..len).map(|_| r.f64()).collect::<Result<_, _>>()?),
+            "<i8" => NpyData::I64(
+                (0..len)
+                    .map(|_| r.u64().map(|x| x as i64))
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => return Err(FormatError::Npz(format!("unsupported dtype {}", descr))),
+        };
+        Ok(Self { shape, data })
+    }
+}
+
+/// Pulls the raw value of `key` out of the python dict literal in a `.npy`
+/// header.
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str, FormatError> {
+    let missing = || FormatError::Npz(format!("header has no {}", key));
+    let start = header.find(&format!("'{}'", key)).ok_or_else(missing)? + key.len() + 2;
+    let rest = header[start..]
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(missing)?;
+    let rest = rest.trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').ok_or_else(missing)? + 1
+    } else {
+        rest.find([',', '}']).ok_or_else(missing)?
+    };
+    Ok(rest[..end].trim())
+}
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL: u32 = 0x0605_4b50;
+const DOS_DATE_1980: u16 = 0x21;
+
+/// Writes a stored (uncompressed) zip archive with one `.npy` per array.
+pub fn write_npz(arrays: &[(String, NpyArray)]) -> Vec<u8> {
+    let mut w = Writer::new();
+    let mut central = Writer::new();
+    for (name, array) in arrays {
+        let name = format!("{}.npy", name);
+        let data = array.to_npy();
+        let crc = crc32(&data);
+        let offset = w.0.len() as u32;
+
+        w.u32(LOCAL_HEADER);
+        w.u16(20); // version needed
+        w.u16(0); // flags
+        w.u16(0); // stored
+        w.u16(0); // time
+        w.u16(DOS_DATE_1980);
+        w.u32(crc);
+        w.u32(data.len() as u32);
+        w.u32(data.len() as u32);
+        w.u16(name.len() as u16);
+        w.u16(0); // extra
+        w.0.extend_from_slice(name.as_bytes());
+        w.0.extend_from_slice(&data);
+
+        central.u32(CENTRAL_HEADER);
+        central.u16(20); // version made by
+        central.u16(20); // version needed
+        central.u16(0); // flags
+        central.u16(0); // stored
+        central.u16(0); // time
+        central.u16(DOS_DATE_1980);
+        central.u32(crc);
+        central.u32(data.len() as u32);
+        central.u32(data.len() as u32);
+        central.u16(name.len() as u16);
+        central.0.extend_from_slice(&[0u8; 8]); // extra, comment, disk, internal attrs
+        central.u32(0); // external attrs
+        central.u32(offset);
+        central.0.extend_from_slice(name.as_bytes());
+    }
+    let central_offset = w.0.len() as u32;
+    w.0.extend_from_slice(&central.0);
+    w.u32(END_OF_CENTRAL);
+    w.u32(0); // disk numbers
+    w.u16(arrays.len() as u16);
+    w.u16(arrays.len() as u16);
+    w.u32(central.0.len() as u32);
+    w.u32(central_offset);
+    w.u16(0); // comment
+    w.0
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<usize, FormatError> {
+    let b = data.get(pos..pos + 2).ok_or(FormatError::Truncated)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]) as usize)
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, FormatError> {
+    let b = data.get(pos..pos + 4).ok_or(FormatError::Truncated)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn u64_at(data: &[u8], pos: usize) -> Result<u64, FormatError> {
+    Ok(u32_at(data, pos)? as u64 | (u32_at(data, pos + 4)? as u64) << 32)
+}
+
+/// Reads every `.npy` in an uncompressed zip archive, as written by
+/// `np.savez` (including its zip64 extra fields).
+pub fn read_npz(data: &[u8]) -> Result<Vec<(String, NpyArray)>, FormatError> {
+    let end = (0..data.len().saturating_sub(21))
+        .rev()
+        .find(|&i| u32_at(data, i).ok() == Some(END_OF_CENTRAL))
+        .ok_or_else(|| FormatError::Npz("not a zip archive".to_string()))?;
+    let count = u16_at(data, end + 10)?;
+    let mut pos = u32_at(data, end + 16)? as usize;
+
+    let mut res = Vec::with_capacity(count);
+    for _ in 0..count {
+        if u32_at(data, pos)? != CENTRAL_HEADER {
+            return Err(FormatError::Npz("corrupt central directory".to_string()));
+        }
+        let method = u16_at(data, pos + 10)?;
+        let crc = u32_at(data, pos + 16)?;
+        let mut size = u32_at(data, pos + 20)? as u64;
+        let name_len = u16_at(data, pos + 28)?;
+        let extra_len = u16_at(data, pos + 30)?;
+        let comment_len = u16_at(data, pos + 32)?;
+        let mut offset = u32_at(data, pos + 42)? as u64;
+        let name = data
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or(FormatError::Truncated)?;
+        let name = String::from_utf8_lossy(name).to_string();
+
+        // zip64: the extra field holds the 64 bit values whose 32 bit slots
+        // are saturated, in the order uncompressed, compressed, offset
+        let mut extra = pos + 46 + name_len;
+        let extra_end = extra + extra_len;
+        while extra + 4 <= extra_end {
+            let id = u16_at(data, extra)?;
+            let len = u16_at(data, extra + 2)?;
+            if id == 1 {
+                let mut p = extra + 4;
+                if u32_at(data, pos + 24)? == u32::MAX {
+                    size = u64_at(data, p)?;
+                    p += 8;
+                }
+                if u32_at(data, pos + 20)? == u32::MAX {
+                    size = u64_at(data, p)?;
+                    p += 8;
+                }
+                if offset == u32::MAX as u64 {
+                    offset = u64_at(data, p)?;
+                }
+            }
+            extra += 4 + len;
+        }
+        pos = extra_end + comment_len;
+
+        if method != 0 {
+            return Err(FormatError::Npz(format!(
+                "{} is compressed, only np.savez (not savez_compressed) archives are supported",
+                name
+            )));
+        }
+        let offset = offset as usize;
+        if u32_at(data, offset)? != LOCAL_HEADER {
+            return Err(FormatError::Npz(format!(
+                "corrupt local header for {}",
+                name
+            )));
+        }
+        let start = offset + 30 + u16_at(data, offset + 26)? + u16_at(data, offset + 28)?;
+        let body = data
+            .get(start..start + size as usize)
+            .ok_or(FormatError::Truncated)?;
+        let computed = crc32(body);
+        if computed != crc {
+            return Err(FormatError::Checksum {
+                stored: crc,
+                computed,
+            });
+        }
+        let name = name.strip_suffix(".npy").unwrap_or(&name).to_string();
+        res.push((name, NpyArray::from_npy(body)?));
+    }
+    Ok(res)
+}
+
+fn layer_shapes(layer: &LayerShape) -> Vec<(String, Vec<usize>)> {
+    match layer.kind {
+        LayerKind::Dense => vec![
+            (
+                format!("{}.weights", layer.name),
+                vec![layer.outputs, layer.inputs],
+            ),
+            (format!("{}.biases", layer.name), vec![layer.outputs]),
+        ],
+        LayerKind::Output => vec![(format!("{}.weights", layer.name), vec![layer.inputs])],
+    }
+}
+
+/// Names and per-bot shapes of the arrays a network is split into, in
+/// `Network::dump` order.
+pub fn array_shapes() -> Vec<(String, Vec<usize>)> {
+    Network::topology().iter().flat_map(layer_shapes).collect()
+}
+
+fn split(networks: &[Network], batch: bool) -> Vec<(String, NpyArray)> {
+    let dumps: Vec<Vec<f32>> = networks.iter().map(|n| n.dump()).collect();
+    let mut res = Vec::new();
+    let mut index = 0;
+    for (name, shape) in array_shapes() {
+        let len = shape.iter().product::<usize>();
+        let mut data = Vec::with_capacity(len * networks.len());
+        for dump in dumps.iter() {
+            data.extend_from_slice(&dump[index..index + len]);
+        }
+        index += len;
+        let shape = if batch {
+            [vec![networks.len()], shape].concat()
+        } else {
+            shape
+        };
+        res.push((name, NpyArray::new(shape, NpyData::F32(data))));
+    }
+    res
+}
+
+pub fn export_network(network: &Network) -> Vec<u8> {
+    write_npz(&split(std::slice::from_ref(network), false))
+}
+
+pub fn export_population(population: &Population) -> Vec<u8> {
+    let networks: Vec<Network> = population.bots.iter().map(|(n, _)| *n).collect();
+    let mut arrays = split(&networks, true);
+    let fitness = population.bots.iter().map(|(_, f)| *f).collect();
+    arrays.push((
+        "fitness".to_string(),
+        NpyArray::new(vec![networks.len()], NpyData::F64(fitness)),
+    ));
+    arrays.push((
+        "generation".to_string(),
+        NpyArray::new(vec![], NpyData::I64(vec![population.generation as i64])),
+    ));
+    write_npz(&arrays)
+}
+
+/// Reads an archive written by `export_network` or `export_population` (or
+/// edited in numpy and saved back with `np.savez`). A single network comes
+/// back as a population of one.
+pub fn import_population(data: &[u8]) -> Result<Population, FormatError> {
+    let arrays = read_npz(data)?;
+    let get = |name: &str| {
+        arrays
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, a)| a)
+            .ok_or_else(|| FormatError::Npz(format!("missing array {}", name)))
+    };
+
+    let shapes = array_shapes();
+    let first = get(&shapes[0].0)?;
+    let batch = first.shape.len() == shapes[0].1.len() + 1;
+    let count = if batch { first.shape[0] } else { 1 };
+
+    let mut dumps = vec![Vec::with_capacity(Network::size()); count];
+    for (name, shape) in shapes.iter() {
+        let array = get(name)?;
+        let expected = if batch {
+            [vec![count], shape.clone()].concat()
+        } else {
+            shape.clone()
+        };
+        if array.shape != expected {
+            return Err(FormatError::Npz(format!(
+                "{} has shape {:?}, expected {:?}",
+                name, array.shape, expected
+            )));
+        }
+        let values = array.data.to_f32();
+        let len = shape.iter().product::<usize>();
+        for (i, dump) in dumps.iter_mut().enumerate() {
+            dump.extend_from_slice(&values[i * len..(i + 1) * len]);
+        }
+    }
+
+    let fitness = match get("fitness") {
+        Ok(a) if a.shape == [count] => a.data.to_f64(),
+        Ok(_) => return Err(FormatError::Npz("fitness has the wrong shape".to_string())),
+        Err(_) => vec![0.0; count],
+    };
+    let generation = match get("generation") {
+        Ok(a) => a.data.to_f64().first().copied().unwrap_or(0.0) as u64,
+        Err(_) => 0,
+    };
+    Ok(Population {
+        generation,
+        metadata: Vec::new(),
+        bots: dumps
+            .iter()
+            .zip(fitness)
+            .map(|(d, f)| (Network::load(d), f))
+            .collect(),
+        extra: Sections::default(),
+    })
+}
+
+pub fn import_network(data: &[u8]) -> Result<Network, FormatError> {
+    let population = import_population(data)?;
+    if population.bots.len() != 1 {
+        return Err(FormatError::Population {
+            expected: 1,
+            found: population.bots.len(),
+        });
+    }
+    Ok(population.bots[0].0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn npy_header() {
+        let array = NpyArray::new(vec![2, 3], NpyData::F32(vec![0.0; 6]));
+        let npy = array.to_npy();
+        assert_eq!((10 + u16::from_le_bytes([npy[8], npy[9]]) as usize) % 64, 0);
+        let header = std::str::from_utf8(&npy[10..npy.len() - 24]).unwrap();
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert_eq!(NpyArray::from_npy(&npy).unwrap(), array);
+
+        let scalar = NpyArray::new(vec![], NpyData::I64(vec![7]));
+        assert_eq!(NpyArray::from_npy(&scalar.to_npy()).unwrap(), scalar);
+        let vector = NpyArray::new(vec![3], NpyData::F64(vec![1.0, 2.0, 3.0]));
+        assert!(String::from_utf8_lossy(&vector.to_npy()[10..64]).contains("(3,)"));
+        assert_eq!(NpyArray::from_npy(&vector.to_npy()).unwrap(), vector);
+    }
+
+    #[test]
+    fn network_round_trip() {
+        let mut rng = thread_rng();
+        let network = Network::new(&mut rng);
+        let loaded = import_network(&export_network(&network)).unwrap();
+        assert_eq!(loaded.dump(), network.dump());
+
+        let arrays = read_npz(&export_network(&network)).unwrap();
+        let a = arrays.iter().find(|(n, _)| n == "a.weights").unwrap();
+        assert_eq!(a.1.shape, vec![32, 16]);
+    }
+
+    #[test]
+    fn population_round_trip() {
+        let mut rng = thread_rng();
+        let population = Population {
+            generation: 12,
+            metadata: Vec::new(),
+            bots: (0..4).map(|i| (Network::new(&mut rng), i as f64)).collect(),
+            extra: Sections::default(),
+        };
+        let loaded = import_population(&export_population(&population)).unwrap();
+        assert_eq!(loaded.generation, 12);
+        assert_eq!(loaded.bots.len(), 4);
+        for ((a, fa), (b, fb)) in population.bots.iter().zip(loaded.bots.iter()) {
+            assert_eq!(a.dump(), b.dump());
+            assert_eq!(fa, fb);
+        }
+        assert!(import_network(&export_population(&population)).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let mut rng = thread_rng();
+        let mut data = export_network(&Network::new(&mut rng));
+        data[100] ^= 1;
+        assert!(import_network(&data).is_err());
+        assert!(read_npz(b"not a zip").is_err());
+
+        let arrays = vec![(
+            "output_layer.weights".to_string(),
+            NpyArray::new(vec![3], NpyData::F32(vec![0.0; 3])),
+        )];
+        assert!(matches!(
+            import_network(&write_npz(&arrays)),
+            Err(FormatError::Npz(_))
+        ));
+    }
+}
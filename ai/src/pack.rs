@@ -0,0 +1,140 @@
+//! Packs network weights into the string literal the `include_data!` macro
+//! of the CodinGame bot (`codingame/src/data.rs`) decodes at compile time.
+//!
+//! Every two bytes form a little endian `u16` stored as one character:
+//! values below 49152 as a three byte UTF-8 sequence with lead byte
+//! `0xe3..=0xec` or `0xee..=0xef` (skipping the surrogates behind `0xed`),
+//! the rest as a four byte sequence `0xf1 0x80..=0x83 ..`. Each continuation
+//! byte carries six bits. A bundled bot spends one character per two bytes
+//! against the CodinGame size limit.
+
+use crate::nn::Network;
+
+/// Values `0..SPLIT` take three bytes, `SPLIT..=0xffff` four.
+const SPLIT: usize = 49152;
+
+fn push(out: &mut Vec<u8>, value: usize) {
+    let (lead, rest) = if value < SPLIT {
+        let high = value >> 12;
+        let lead = if high <= 9 { 0xe3 + high } else { 0xe4 + high };
+        (vec![lead as u8], value & 0xfff)
+    } else {
+        let value = value - SPLIT;
+        (vec![0xf1, 0x80 + (value >> 12) as u8], value & 0xfff)
+    };
+    out.extend(lead);
+    out.push(0x80 + (rest >> 6) as u8);
+    out.push(0x80 + (rest & 0x3f) as u8);
+}
+
+pub fn pack(bytes: &[u8]) -> String {
+    let mut out = Vec::with_capacity(bytes.len() * 3 / 2 + 3);
+    for pair in bytes.chunks(2) {
+        let value = pair[0] as usize | (*pair.get(1).unwrap_or(&0) as usize) << 8;
+        push(&mut out, value);
+    }
+    String::from_utf8(out).expect("packed data is valid UTF-8")
+}
+
+/// Decodes `size` bytes like `include_data!`.
+pub fn unpack(packed: &str, size: usize) -> Vec<u8> {
+    let bytes = packed.as_bytes();
+    let mut out = Vec::with_capacity(size + 1);
+    let mut i = 0;
+    while out.len() < size {
+        let high = match bytes[i] as usize {
+            0xf1 => {
+                i += 1;
+                SPLIT + ((bytes[i] as usize - 0x80) << 12)
+            }
+            h if h <= 0xec => (h - 0xe3) << 12,
+            h => (h - 0xe4) << 12,
+        };
+        let value = high + ((bytes[i + 1] as usize - 0x80) << 6) + (bytes[i + 2] as usize - 0x80);
+        i += 3;
+        out.push(value as u8);
+        out.push((value >> 8) as u8);
+    }
+    out.truncate(size);
+    out
+}
+
+/// Little endian bytes of the weights, in `Network::dump` order, which is
+/// the order `Network::load` of the bot reads.
+pub fn network_bytes(network: &Network) -> Vec<u8> {
+    network
+        .dump()
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .collect()
+}
+
+/// `codingame/src/weights.rs` for `network`, trained as described by
+/// `origin` to search with `points_weight`. Without an origin the file is
+/// marked as a placeholder, which the bundler refuses to submit.
+pub fn weights_source(network: &Network, points_weight: f32, origin: Option<&str>) -> String {
+    let bytes = network_bytes(network);
+    let header = match origin {
+        Some(origin) => format!("// Generated by `ai pack` from {}, do not edit.", origin),
+        None => "// Placeholder, not a trained bot: replace it with the output of `ai pack`\n\
+                 // for a trained population before submitting."
+            .to_string(),
+    };
+    format!(
+        "{}\n\
+         pub const ORIGIN: Option<&str> = {:?};\n\
+         pub const POINTS_WEIGHT: f32 = {:?};\n\
+         pub const WEIGHTS_SIZE: usize = {};\n\
+         pub const WEIGHTS: &str = \"{}\";\n",
+        header,
+        origin,
+        points_weight,
+        bytes.len(),
+        pack(&bytes)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn round_trip() {
+        let all: Vec<u8> = (0..=u16::MAX).flat_map(|v| v.to_le_bytes()).collect();
+        let packed = pack(&all);
+        assert_eq!(packed.chars().count(), 65536);
+        assert_eq!(unpack(&packed, all.len()), all);
+        assert!(!packed.contains('/') && !packed.contains('"') && !packed.contains(' '));
+
+        let odd = [1, 2, 255];
+        assert_eq!(unpack(&pack(&odd), 3), odd);
+        let network = Network::new(&mut StdRng::seed_from_u64(1));
+        let bytes = network_bytes(&network);
+        assert_eq!(bytes.len(), Network::size() * 4);
+        let weights: Vec<f32> = unpack(&pack(&bytes), bytes.len())
+            .chunks(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        assert_eq!(weights, network.dump());
+    }
+
+    mod codingame {
+        // the macro and the weights the bot is built with
+        include!("../../codingame/src/data.rs");
+        include!("../../codingame/src/weights.rs");
+
+        #[test]
+        fn include_data() {
+            // decoded as the bot does
+            const DATA: [f32; WEIGHTS_SIZE / 4] = include_data!(WEIGHTS_SIZE, WEIGHTS);
+            let bytes: Vec<u8> = DATA.iter().flat_map(|w| w.to_le_bytes()).collect();
+            assert_eq!(bytes, super::unpack(WEIGHTS, WEIGHTS_SIZE));
+            assert_eq!(super::pack(&bytes), WEIGHTS);
+            assert_eq!(DATA.len(), crate::nn::Network::size());
+            // the placeholder has no trained weights
+            assert!(ORIGIN.is_some() || DATA.iter().all(|w| *w == 0.0));
+            assert!(POINTS_WEIGHT.is_finite());
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Terminal rendering of boards, with the tile colours of the original game.
+//!
+//! `Data` also prints as plain columns through `Display` and as one line
+//! through `Data::notation`.
+
+use crate::engine::Data;
+
+/// How `render` draws a board.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    /// 24-bit ANSI colours.
+    pub color: bool,
+    /// Box drawing characters instead of ASCII.
+    pub unicode: bool,
+}
+
+/// Characters of a cell, enough for 32768 with a space on each side.
+const WIDTH: usize = 7;
+
+/// Background and text colour of a tile as `0xrrggbb`.
+fn colors(tile: u32) -> (u32, u32) {
+    let background = match tile {
+        0 => 0xcdc1b4,
+        1 => 0xeee4da,
+        2 => 0xede0c8,
+        3 => 0xf2b179,
+        4 => 0xf59563,
+        5 => 0xf67c5f,
+        6 => 0xf65e3b,
+        7 => 0xedcf72,
+        8 => 0xedcc61,
+        9 => 0xedc850,
+        10 => 0xedc53f,
+        11 => 0xedc22e,
+        _ => 0x3c3a32,
+    };
+    let text = if tile <= 2 { 0x776e65 } else { 0xf9f6f2 };
+    (background, text)
+}
+
+fn rgb(color: u32) -> String {
+    format!("{};{};{}", color >> 16, (color >> 8) & 0xff, color & 0xff)
+}
+
+/// Draws `data` in a grid, one line per row of cells, ending with a newline.
+pub fn render(data: &Data, style: Style) -> String {
+    let (h, v, corners) = if style.unicode {
+        (
+            '─',
+            '│',
+            [['┌', '┬', '┐'], ['├', '┼', '┤'], ['└', '┴', '┘']],
+        )
+    } else {
+        ('-', '|', [['+'; 3]; 3])
+    };
+    let border = |[left, middle, right]: [char; 3]| {
+        let cell: String = std::iter::repeat_n(h, WIDTH).collect();
+        format!(
+            "{}{}{}\n",
+            left,
+            [cell.as_str(); 4].join(&middle.to_string()),
+            right
+        )
+    };
+    let mut out = border(corners[0]);
+    for row in 0..4 {
+        out.push(v);
+        for col in 0..4 {
+            let tile = data.tile(row, col);
+            let text = if tile == 0 {
+                String::new()
+            } else {
+                (1u32 << tile).to_string()
+            };
+            let cell = format!("{:^width$}", text, width = WIDTH);
+            if style.color {
+                let (background, foreground) = colors(tile);
+                out.push_str(&format!(
+                    "\x1b[1;48;2;{}m\x1b[38;2;{}m{}\x1b[0m",
+                    rgb(background),
+                    rgb(foreground),
+                    cell
+                ));
+            } else {
+                out.push_str(&cell);
+            }
+            out.push(v);
+        }
+        out.push('\n');
+        out.push_str(&border(corners[if row == 3 { 2 } else { 1 }]));
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let data: Data = "0000/0100/0000/b00f".parse().unwrap();
+        let ascii = super::render(&data, Style::default());
+        let lines: Vec<&str> = ascii.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], "+-------+-------+-------+-------+");
+        assert_eq!(lines[3], "|       |   2   |       |       |");
+        assert_eq!(lines[7], "| 2048  |       |       | 32768 |");
+
+        let unicode = super::render(
+            &data,
+            Style {
+                unicode: true,
+                ..Style::default()
+            },
+        );
+        assert!(unicode.starts_with("┌───────┬"));
+        assert!(unicode.ends_with("┴───────┘\n"));
+        assert!(unicode.contains("│ 2048  │"));
+
+        let color = super::render(
+            &data,
+            Style {
+                color: true,
+                unicode: false,
+            },
+        );
+        assert!(color.contains("\x1b[1;48;2;237;194;46m\x1b[38;2;249;246;242m 2048  \x1b[0m"));
+        assert!(color.contains("\x1b[1;48;2;238;228;218m\x1b[38;2;119;110;101m   2   \x1b[0m"));
+        assert_eq!(color.lines().count(), 9);
+    }
+}
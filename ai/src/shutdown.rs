@@ -0,0 +1,42 @@
+//! Graceful stop of `ai train` on SIGINT or SIGTERM.
+//!
+//! The first signal only raises a flag: the trainer finishes the generation
+//! it is evaluating, writes its metrics and a final checkpoint and prints a
+//! summary. A second signal exits at once; checkpoints are written
+//! atomically, so the last one on disk stays intact.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+/// Exit status after a forced stop, as for a shell killed by SIGINT.
+pub const FORCED_EXIT: i32 = 130;
+
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+static INSTALL: Once = Once::new();
+
+/// Installs the handler; later calls do nothing.
+pub fn install() -> Result<(), String> {
+    let mut result = Ok(());
+    INSTALL.call_once(|| {
+        result = ctrlc::set_handler(|| {
+            if request() == 1 {
+                eprintln!("stopping after this generation, signal again to exit immediately");
+            } else {
+                eprintln!("exiting immediately");
+                std::process::exit(FORCED_EXIT);
+            }
+        })
+        .map_err(|e| format!("failed to install signal handler: {}", e));
+    });
+    result
+}
+
+/// Counts a stop request, as the signal handler does. Returns how many
+/// there were.
+pub fn request() -> usize {
+    SIGNALS.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+pub fn requested() -> bool {
+    SIGNALS.load(Ordering::SeqCst) > 0
+}
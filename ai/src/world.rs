@@ -1,201 +1,828 @@
+use crate::cmaes::SepCmaEs;
 use crate::configs::*;
-use crate::progress::Pbar;
-use crate::{engine::random_seed, game::Game, nn::Network};
-use once_cell::sync::Lazy;
-use rand::distributions::WeightedIndex;
+use crate::curriculum::{Pool, Schedule};
+use crate::dist::Coordinator;
+use crate::diversity::{blend, niche, novelty, Behaviour, Niching, BEHAVIOUR};
+use crate::engine::{Board, Data, SEED_MOD};
+use crate::eval::par_map;
+use crate::format::{
+    checkpoint_path, write_checkpoint, FormatError, Population, Reader, Sections, Writer,
+};
+use crate::genetic::{Mutation, Selector};
+use crate::hall::{hall_path, Entry, HallOfFame};
+use crate::metrics::Record;
+use crate::{game::Game, nn::Network};
 use rand::prelude::*;
-use std::path::Path;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::sync::Mutex;
-use std::thread;
+use rand_chacha::ChaCha8Rng;
+use std::time::Instant;
+
+pub const TAG_CMA: [u8; 4] = *b"CMAS";
+/// Per-bot step sizes of `Mutation::SelfAdaptive`.
+pub const TAG_STEP: [u8; 4] = *b"STEP";
+/// State of `World::rng`.
+pub const TAG_RNG: [u8; 4] = *b"RNGS";
+/// `World::starts`.
+pub const TAG_STARTS: [u8; 4] = *b"STRT";
+/// `World::pool`.
+pub const TAG_POOL: [u8; 4] = *b"POOL";
+/// `World::archive`.
+pub const TAG_NOVELTY: [u8; 4] = *b"NOVA";
 
 pub struct World {
-    pub bots: Arc<Vec<Game>>,
+    pub bots: Vec<Game>,
     pub generation: usize,
+    pub config: Config,
+    /// Search distribution when `config.optimizer` is CMA-ES.
+    pub cma: Option<SepCmaEs>,
+    /// Draws the initial networks, the game seeds and every breeding
+    /// decision, seeded from `config.seed`.
+    pub rng: ChaCha8Rng,
+    /// Boards the games of the current generation start from, drawn when it
+    /// is bred.
+    pub starts: Vec<Board>,
+    /// When this process started training, for `Record::total`.
+    pub started: Instant,
+    pub hall: HallOfFame,
+    /// What the parents are selected on, aligned with `bots`: the fitness
+    /// after niching and novelty, see `diversity`.
+    pub scores: Vec<f64>,
+    /// Novelty search archive of past behaviours.
+    pub archive: Vec<Behaviour>,
+    /// Archived starting positions of the curriculum.
+    pub pool: Pool,
 }
 
-pub fn has_path() -> Option<String> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() > 1 {
-        Some(args[1].clone())
-    } else {
-        None
+fn seeded(config: &Config) -> ChaCha8Rng {
+    match config.seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_entropy(),
     }
 }
 
-static PATH: Lazy<PathBuf> = Lazy::new(|| {
-    let buf = if let Some(path) = has_path() {
-        path
-    } else {
-        println!("Please input the path to save the data: ");
-        let mut buf = String::new();
-        std::io::stdin().read_line(&mut buf).unwrap();
-        buf
-    };
-    let path = Path::new(buf.trim());
-    if !path.parent().unwrap().exists() {
-        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
-    }
-    path.to_path_buf()
-});
+fn encode_rng(rng: &ChaCha8Rng) -> Vec<u8> {
+    let mut w = Writer::new();
+    rng.get_seed().iter().for_each(|b| w.u8(*b));
+    w.u64(rng.get_stream());
+    let pos = rng.get_word_pos();
+    w.u64(pos as u64);
+    w.u64((pos >> 64) as u64);
+    w.0
+}
+
+fn decode_rng(data: &[u8]) -> Result<ChaCha8Rng, FormatError> {
+    let mut r = Reader::new(data);
+    let mut seed = [0u8; 32];
+    seed.copy_from_slice(r.bytes(32)?);
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    rng.set_stream(r.u64()?);
+    let low = r.u64()? as u128;
+    let high = r.u64()? as u128;
+    rng.set_word_pos(low | high << 64);
+    Ok(rng)
+}
 
 impl World {
-    pub fn new() -> Self {
-        if PATH.exists() {
-            Self::load_from_file()
+    pub fn new(config: Config) -> Result<Self, FormatError> {
+        let mut world = if config.path.exists() {
+            Self::load_from_file(config)?
         } else {
-            let mut bots = Vec::with_capacity(NUM_BOTS);
-            let mut rng = thread_rng();
-            for _ in 0..NUM_BOTS {
-                let network = Network::new(&mut rng);
-                bots.push(Game::new(network));
+            Self::random(config)
+        };
+        let path = hall_path(&world.config.path);
+        if path.exists() {
+            let population = Population::read(&path)?;
+            world.hall = HallOfFame::from_population(&population, world.config.hall_size)?;
+        }
+        Ok(world)
+    }
+
+    pub fn random(config: Config) -> Self {
+        let mut rng = seeded(&config);
+        match config.optimizer {
+            Optimizer::Genetic => {
+                let mut bots = Vec::with_capacity(config.population());
+                for _ in 0..config.population() {
+                    let network = Network::new(&mut rng);
+                    bots.push(Game::new(network));
+                }
+                let mut world = World {
+                    bots,
+                    generation: 0,
+                    hall: HallOfFame::new(config.hall_size, config.validation_seeds.clone()),
+                    pool: Pool::new(config.curriculum_pool),
+                    config,
+                    cma: None,
+                    rng,
+                    starts: vec![],
+                    started: Instant::now(),
+                    scores: vec![],
+                    archive: vec![],
+                };
+                world.draw_starts();
+                world
             }
-            World {
-                bots: Arc::new(bots),
-                generation: 0,
+            Optimizer::CmaEs => {
+                let cma = SepCmaEs::new(
+                    &Network::new(&mut rng),
+                    config.cma_sigma,
+                    config.cma_population,
+                );
+                Self::from_cma(cma, 0, config, rng)
             }
         }
     }
 
-    pub fn run(&mut self, workers: usize) {
-        random_seed();
-        let index = Arc::new(Mutex::new(0));
-        let mut handles = vec![];
-        println!("Running Generation {}...", self.generation);
-        let pbar = Pbar::new();
-        for _ in 0..workers {
-            let bots = self.bots.clone();
-            let index = index.clone();
-            let pbar = pbar.clone();
-            let handle = thread::spawn(move || loop {
-                let index = {
-                    let mut index = index.lock().unwrap();
-                    let i = *index;
-                    if i >= NUM_BOTS {
-                        break;
-                    }
-                    *index += 1;
-                    pbar.inc(1);
-                    i
+    fn from_cma(cma: SepCmaEs, generation: usize, config: Config, mut rng: ChaCha8Rng) -> Self {
+        let bots = cma.ask(&mut rng).into_iter().map(Game::new).collect();
+        let mut world = World {
+            bots,
+            generation,
+            hall: HallOfFame::new(config.hall_size, config.validation_seeds.clone()),
+            pool: Pool::new(config.curriculum_pool),
+            config,
+            cma: Some(cma),
+            rng,
+            starts: vec![],
+            started: Instant::now(),
+            scores: vec![],
+            archive: vec![],
+        };
+        world.draw_starts();
+        world
+    }
+
+    /// Draws the starts of the generation: fresh boards, and pool boards for
+    /// the share of `config.curriculum`.
+    fn draw_starts(&mut self) {
+        let share = self.config.curriculum.share(self.generation);
+        self.starts = (0..self.config.seeds_per_generation.max(1))
+            .map(|_| {
+                let pooled = if share > 0.0 && self.rng.gen_bool(share) {
+                    self.pool.sample(&mut self.rng)
+                } else {
+                    None
                 };
-                unsafe {
-                    let bot = bots.get_unchecked(index) as *const Game as *mut Game;
-                    (*bot).run_with_ai(4);
-                }
-            });
-            handles.push(handle);
+                pooled.unwrap_or_else(|| Board::new(self.rng.gen_range(0..SEED_MOD)))
+            })
+            .collect();
+    }
+
+    /// Evaluates the generation and returns its metrics.
+    pub fn run(&mut self, workers: usize) -> Record {
+        let start = Instant::now();
+        let starts = self.starts.clone();
+        let objective = self.config.objective();
+        println!("Running Generation {}...", self.generation);
+        let evaluated = par_map(&self.bots, workers, |bot| {
+            let mut bot = *bot;
+            bot.evaluate_from(&starts, objective);
+            bot
+        });
+        self.finish(evaluated, start, workers)
+    }
+
+    /// Like `run`, but plays the games on the workers of `coordinator`. The
+    /// hall of fame is still validated locally on `workers` threads.
+    pub fn run_distributed(&mut self, coordinator: &Coordinator, workers: usize) -> Record {
+        let start = Instant::now();
+        println!(
+            "Running Generation {} on {} workers...",
+            self.generation,
+            coordinator.workers()
+        );
+        let evaluated = coordinator.evaluate(&self.bots, &self.starts, self.config.objective());
+        self.finish(evaluated, start, workers)
+    }
+
+    fn finish(&mut self, evaluated: Vec<Game>, start: Instant, workers: usize) -> Record {
+        self.bots = evaluated;
+        // stable, so ties keep their order whatever the worker count
+        self.bots.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+        let mut record = Record::new(
+            self.generation,
+            &self.bots,
+            self.bots.len() * self.starts.len(),
+            start.elapsed(),
+            self.started.elapsed(),
+        );
+        record.operators = self.operators();
+        (record.species, record.novelty) = self.score(workers);
+        println!(
+            "Diversity: distance {:.4}, {} species, novelty {:.4}",
+            record.distance, record.species, record.novelty
+        );
+        self.update_hall(workers);
+        let game = self.bots[0].trajectory(self.config.depth, self.starts[0]);
+        self.log(game.last().unwrap());
+        if self.config.curriculum != Schedule::Off {
+            // counted before the pool changes, it is as drawn from
+            let pooled = self
+                .starts
+                .iter()
+                .filter(|b| self.pool.boards.contains(b))
+                .count();
+            self.pool.add_game(&game, self.config.curriculum_min_tile);
+            println!(
+                "Curriculum: {} of {} starts from a pool of {} boards",
+                pooled,
+                self.starts.len(),
+                self.pool.boards.len()
+            );
         }
-        handles.into_iter().for_each(|h| h.join().unwrap());
-        pbar.finish();
-        let bots = Arc::get_mut(&mut self.bots).unwrap();
-        bots.sort_by_key(|b| -(b.score as isize));
-        self.log();
+        record
     }
 
-    pub fn log(&self) {
-        let mut bot = self.bots.get(0).unwrap().clone();
-        println!("{}", bot.run_with_ai(4).data);
-        println!("Generation{} max score: {}", self.generation, bot.score);
+    /// Fills `scores` from the evaluated bots and grows the novelty archive.
+    /// Returns the species count and the mean novelty.
+    fn score(&mut self, workers: usize) -> (usize, f64) {
+        let fitness: Vec<f64> = self.bots.iter().map(|b| b.fitness).collect();
+        if self.cma.is_some() {
+            // CMA-ES only uses the ranking
+            self.scores = fitness;
+            return (0, 0.0);
+        }
+        let (scores, species) = match self.config.niching {
+            Niching::None => (fitness, 0),
+            niching => {
+                let genomes: Vec<Vec<f32>> = self.bots.iter().map(|b| b.network.dump()).collect();
+                niche(niching, &fitness, &genomes, workers)
+            }
+        };
+        self.scores = scores;
+        if self.config.novelty <= 0.0 {
+            return (species, 0.0);
+        }
+        let behaviours: Vec<Behaviour> = self.bots.iter().map(|b| b.behaviour).collect();
+        let novelty = novelty(
+            &behaviours,
+            &self.archive,
+            self.config.novelty_neighbours,
+            workers,
+        );
+        self.scores = blend(&self.scores, &novelty, self.config.novelty);
+        let most_novel = (0..novelty.len()).max_by(|a, b| novelty[*a].total_cmp(&novelty[*b]));
+        if let Some(i) = most_novel {
+            self.archive.push(behaviours[i]);
+        }
+        let excess = self
+            .archive
+            .len()
+            .saturating_sub(self.config.novelty_archive);
+        self.archive.drain(..excess);
+        let mean = novelty.iter().sum::<f64>() / novelty.len().max(1) as f64;
+        (species, mean)
     }
 
-    pub fn dump(&self) -> Vec<u8> {
-        let mut res = Vec::with_capacity(NUM_BOTS * Network::size() + 4);
-        for bot in self.bots.iter() {
-            let data = bot.network.dump();
-            res.extend_from_slice(&data);
-        }
-        let mut res = unsafe {
-            let ptr = res.as_ptr() as *mut u8;
-            std::mem::forget(res);
-            let len = NUM_BOTS * Network::size() * 4;
-            Vec::from_raw_parts(ptr, len, len + 4)
-        };
-        let generation = self.generation as u32;
-        res.extend_from_slice(&generation.to_le_bytes());
-        res
-    }
-
-    pub fn load(data: &[u8]) -> Self {
-        unsafe {
-            let ptr = data.as_ptr() as *mut f32;
-            let len = data.len() / 4;
-            let slice = std::slice::from_raw_parts(ptr, len);
-            let mut bots = Vec::with_capacity(NUM_BOTS);
-            for i in 0..NUM_BOTS {
-                let network = Network::load(&slice[i * Network::size()..]);
-                bots.push(Game::new(network));
+    /// Validates the best bots of the generation and archives those that
+    /// beat the hall of fame.
+    fn update_hall(&mut self, workers: usize) {
+        if self.hall.size == 0 {
+            return;
+        }
+        let objective = self.config.objective();
+        // entries scored another way do not compare with the new candidates
+        if self.hall.seeds != self.config.validation_seeds || self.hall.objective != Some(objective)
+        {
+            let seeds = self.config.validation_seeds.clone();
+            self.hall.revalidate(seeds, objective, workers);
+        }
+        let candidates = self.config.hall_candidates.min(self.bots.len());
+        for bot in self.bots[..candidates].iter() {
+            if self.hall.contains(&bot.network) {
+                continue;
             }
-            let mut buf = [0u8; 4];
-            buf.copy_from_slice(&data[NUM_BOTS * Network::size() * 4..]);
-            World {
-                bots: Arc::new(bots),
-                generation: u32::from_le_bytes(buf) as usize,
+            let fitness = self.hall.validate(&bot.network, objective, workers);
+            self.hall.insert(Entry {
+                network: bot.network,
+                generation: self.generation,
+                fitness,
+            });
+        }
+        if let Some(best) = self.hall.best() {
+            println!(
+                "Hall of fame best: {} (generation {}, {} validation seeds)",
+                best.fitness,
+                best.generation,
+                self.hall.seeds.len()
+            );
+        }
+    }
+
+    /// How the current bots were produced.
+    pub fn operators(&self) -> Vec<(String, usize)> {
+        let ops = match (&self.cma, self.generation) {
+            (Some(cma), _) => vec![("sample", cma.lambda)],
+            (None, 0) => vec![("random", self.bots.len())],
+            (None, _) => {
+                let elitism = self.config.elitism.min(self.config.do_change);
+                vec![
+                    ("mutation", self.config.do_mutation),
+                    ("crossover", self.config.do_cross * 2),
+                    ("copy", self.config.do_change - elitism),
+                    ("elite", elitism),
+                ]
             }
+        };
+        ops.into_iter().map(|(k, n)| (k.to_string(), n)).collect()
+    }
+
+    /// Prints the final board of the champion's game and its fitness.
+    pub fn log(&self, end: &Board) {
+        println!("{}", end.data);
+        println!(
+            "Generation{} max fitness: {} ({} over {} seeds)",
+            self.generation,
+            self.bots[0].fitness,
+            self.config.aggregate,
+            self.config.seeds_per_generation.max(1)
+        );
+    }
+
+    pub fn to_population(&self) -> Population {
+        let mut extra = Sections::default();
+        if let Some(cma) = &self.cma {
+            extra.push(TAG_CMA, cma.encode());
+        }
+        if let Mutation::SelfAdaptive { .. } = self.config.mutation {
+            let mut w = Writer::new();
+            w.f32s(&self.bots.iter().map(|b| b.step).collect::<Vec<_>>());
+            extra.push(TAG_STEP, w.0);
+        }
+        extra.push(TAG_RNG, encode_rng(&self.rng));
+        let mut w = Writer::new();
+        w.u64(self.starts.len() as u64);
+        for board in self.starts.iter() {
+            w.u64(board.seed);
+            w.u64(board.data.0);
+        }
+        extra.push(TAG_STARTS, w.0);
+        if !self.pool.boards.is_empty() {
+            extra.push(TAG_POOL, self.pool.encode());
         }
+        if !self.archive.is_empty() {
+            let mut w = Writer::new();
+            w.f32s(&self.archive.concat());
+            extra.push(TAG_NOVELTY, w.0);
+        }
+        let metadata = [
+            ("optimizer", self.config.optimizer.name().to_string()),
+            ("elitism", self.config.elitism.to_string()),
+            ("selection", self.config.selection.to_string()),
+            ("mutation", self.config.mutation.to_string()),
+            ("crossover", self.config.crossover.to_string()),
+            ("niching", self.config.niching.to_string()),
+            ("novelty", self.config.novelty.to_string()),
+            ("curriculum", self.config.curriculum.to_string()),
+            (
+                "seeds_per_generation",
+                self.config.seeds_per_generation.to_string(),
+            ),
+            ("aggregate", self.config.aggregate.to_string()),
+            ("fitness", self.config.fitness.to_string()),
+            ("points_weight", self.config.points_weight.to_string()),
+            ("do_cross", self.config.do_cross.to_string()),
+            ("do_mutation", self.config.do_mutation.to_string()),
+            ("do_change", self.config.do_change.to_string()),
+            ("depth", self.config.depth.to_string()),
+        ];
+        Population {
+            generation: self.generation as u64,
+            metadata: metadata
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+            bots: self
+                .bots
+                .iter()
+                .map(|bot| (bot.network, bot.fitness))
+                .collect(),
+            extra,
+        }
+    }
+
+    pub fn dump(&self) -> Vec<u8> {
+        self.to_population().encode()
     }
 
-    pub fn save(&self) {
-        let data = self.dump();
-        std::fs::write(&*PATH, data).unwrap();
+    pub fn load(data: &[u8], config: Config) -> Result<Self, FormatError> {
+        let population = Population::decode(data)?;
+        // a checkpoint resumes its own random stream, whatever `config.seed`
+        let rng = match population.extra.get(TAG_RNG) {
+            Some(state) => decode_rng(state)?,
+            None => seeded(&config),
+        };
+        let generation = population.generation as usize;
+        let cma = match population.extra.get(TAG_CMA) {
+            Some(state) if config.optimizer == Optimizer::CmaEs => Some(SepCmaEs::decode(state)?),
+            _ => None,
+        };
+        if config.optimizer == Optimizer::CmaEs && cma.is_none() {
+            // switching from the genetic algorithm: start around its best bot
+            let best = population.best().ok_or(FormatError::Population {
+                expected: config.population(),
+                found: 0,
+            })?;
+            let cma = SepCmaEs::new(
+                &population.bots[best].0,
+                config.cma_sigma,
+                config.cma_population,
+            );
+            return Ok(Self::from_cma(cma, generation, config, rng));
+        }
+        let expected = cma.as_ref().map_or(config.population(), |c| c.lambda);
+        if population.bots.len() != expected {
+            return Err(FormatError::Population {
+                expected,
+                found: population.bots.len(),
+            });
+        }
+        let steps = match population.extra.get(TAG_STEP) {
+            Some(data) => Reader::new(data).f32s(data.len() / 4)?,
+            None => vec![],
+        };
+        let bots = population
+            .bots
+            .into_iter()
+            .enumerate()
+            .map(|(i, (network, fitness))| Game {
+                fitness,
+                step: steps.get(i).copied().unwrap_or(0.0),
+                ..Game::new(network)
+            })
+            .collect();
+        let mut world = World {
+            bots,
+            generation,
+            hall: HallOfFame::new(config.hall_size, config.validation_seeds.clone()),
+            pool: Pool::new(config.curriculum_pool),
+            config,
+            cma,
+            rng,
+            starts: vec![],
+            started: Instant::now(),
+            scores: vec![],
+            archive: vec![],
+        };
+        if let Some(data) = population.extra.get(TAG_POOL) {
+            world.pool = Pool::decode(data, world.config.curriculum_pool)?;
+        }
+        match population.extra.get(TAG_STARTS) {
+            Some(data) => {
+                let mut r = Reader::new(data);
+                for _ in 0..r.u64()? {
+                    world
+                        .starts
+                        .push(Board::from_data(r.u64()?, Data(r.u64()?)));
+                }
+            }
+            None => world.draw_starts(),
+        }
+        if let Some(data) = population.extra.get(TAG_NOVELTY) {
+            let archive = Reader::new(data).f32s(data.len() / 4)?;
+            world.archive = archive
+                .chunks_exact(BEHAVIOUR)
+                .map(|c| c.try_into().unwrap())
+                .collect();
+        }
+        Ok(world)
     }
 
-    pub fn load_from_file() -> Self {
-        let data = std::fs::read(&*PATH).unwrap();
-        World::load(&data)
+    /// Writes the population and, next to it, the hall of fame.
+    pub fn save(&self) -> std::io::Result<()> {
+        write_checkpoint(&self.config.path, &self.dump(), self.config.checkpoints)?;
+        if self.hall.size > 0 {
+            let data = self.hall.to_population().encode();
+            write_checkpoint(&hall_path(&self.config.path), &data, 1)?;
+        }
+        Ok(())
     }
 
-    pub fn index(&self) -> WeightedIndex<usize> {
-        let mut weights = Vec::with_capacity(NUM_BOTS);
-        let mut min = self.bots.last().unwrap().score;
-        min *= 9;
-        min /= 10;
-        for bot in self.bots.iter() {
-            let socre = bot.score;
-            weights.push(socre - min);
+    /// Loads `config.path`, falling back to the rotated checkpoints when it
+    /// is unreadable.
+    pub fn load_from_file(config: Config) -> Result<Self, FormatError> {
+        let mut first_error = None;
+        for i in 0..config.checkpoints.max(1) {
+            let path = checkpoint_path(&config.path, i);
+            let result = std::fs::read(&path)
+                .map_err(FormatError::from)
+                .and_then(|data| World::load(&data, config.clone()));
+            match result {
+                Ok(world) => {
+                    if i > 0 {
+                        eprintln!(
+                            "{} is unreadable, resuming from {}",
+                            config.path.display(),
+                            path.display()
+                        );
+                    }
+                    return Ok(world);
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
         }
-        WeightedIndex::new(&weights).unwrap()
+        Err(first_error.unwrap())
     }
 
+    #[allow(clippy::manual_is_multiple_of)]
     pub fn update(&mut self) {
-        let index = self.index();
-        let mut rng = thread_rng();
-        let mut bots = Vec::with_capacity(NUM_BOTS);
-        for _ in 0..DO_MUTATION {
-            let i = index.sample(&mut rng);
-            let bot = self.bots.get(i).unwrap();
-            let mut network = bot.network;
-            network.mutate(&mut rng);
-            bots.push(Game::new(network));
-        }
-        for _ in 0..DO_CROSS {
-            let i = index.sample(&mut rng);
-            let j = index.sample(&mut rng);
-            let bot1 = self.bots.get(i).unwrap();
-            let bot2 = self.bots.get(j).unwrap();
-            let networks = bot1.network.cross(&bot2.network, &mut rng);
-            bots.push(Game::new(networks.0));
-            bots.push(Game::new(networks.1));
-        }
-        for _ in 0..DO_CHANGE {
-            let i = index.sample(&mut rng);
-            let bot = self.bots.get(i).unwrap();
-            bots.push(bot.clone());
-        }
-        self.bots = Arc::new(bots);
+        let bots = match self.cma.as_mut() {
+            Some(cma) => {
+                let ranked: Vec<Network> = self.bots.iter().map(|b| b.network).collect();
+                cma.tell(&ranked);
+                cma.ask(&mut self.rng).into_iter().map(Game::new).collect()
+            }
+            None => self.breed(),
+        };
+        self.bots = bots;
         self.generation += 1;
-        if self.generation % SAVE_INTERVAL == 0 {
-            self.save();
+        self.draw_starts();
+        if self.generation % self.config.save_interval.max(1) == 0 {
+            if let Err(e) = self.save() {
+                eprintln!("failed to save {}: {}", self.config.path.display(), e);
+            }
+        }
+    }
+
+    fn breed(&mut self) -> Vec<Game> {
+        // a freshly loaded world has not been scored yet
+        let scores = if self.scores.len() == self.bots.len() {
+            self.scores.clone()
+        } else {
+            self.bots.iter().map(|b| b.fitness).collect()
+        };
+        let selector = Selector::new(self.config.selection, &scores);
+        let rng = &mut self.rng;
+        let mut bots = Vec::with_capacity(self.config.population());
+        for _ in 0..self.config.do_mutation {
+            let bot = &self.bots[selector.sample(rng)];
+            let mut genes = bot.network.dump();
+            let step = self.config.mutation.apply(&mut genes, bot.step, rng);
+            bots.push(Game {
+                step,
+                ..Game::new(Network::load(&genes))
+            });
+        }
+        for _ in 0..self.config.do_cross {
+            let bot1 = &self.bots[selector.sample(rng)];
+            let bot2 = &self.bots[selector.sample(rng)];
+            let (genes1, genes2) =
+                self.config
+                    .crossover
+                    .apply(&bot1.network.dump(), &bot2.network.dump(), rng);
+            let step = (bot1.step * bot2.step).sqrt();
+            for genes in [genes1, genes2] {
+                bots.push(Game {
+                    step,
+                    ..Game::new(Network::load(&genes))
+                });
+            }
+        }
+        let elitism = self.config.elitism.min(self.config.do_change);
+        bots.extend_from_slice(&self.bots[..elitism]);
+        for _ in elitism..self.config.do_change {
+            bots.push(self.bots[selector.sample(rng)]);
         }
+        bots
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::eval::Fitness;
+    use crate::genetic::{Crossover, Selection};
     #[test]
     fn test() {
-        let mut world = World::new();
-        world.run(7);
+        let dir = std::env::temp_dir().join(format!("2048ai-world-{}", std::process::id()));
+        let mut config = Config {
+            depth: 1,
+            seed: Some(1),
+            path: dir.join("population.bin"),
+            ..Config::default()
+        };
+        config.set_population(8).unwrap();
+        let mut world = World::random(config.clone());
+        world.run(2);
+        assert!(world.bots[0].fitness >= world.bots[7].fitness);
+        world.update();
+        assert_eq!(world.bots.len(), 8);
+
+        let mut loaded = World::new(config.clone()).unwrap();
+        assert_eq!(loaded.generation, 1);
+        assert_eq!(loaded.hall.entries.len(), 1);
+        assert_eq!(
+            loaded.hall.best().unwrap().network.dump(),
+            world.hall.best().unwrap().network.dump()
+        );
+        assert_eq!(loaded.bots[3].network.dump(), world.bots[3].network.dump());
+        assert_eq!(loaded.starts, world.starts);
+
+        // the resumed run continues exactly like the uninterrupted one
+        for w in [&mut world, &mut loaded] {
+            w.run(2);
+            w.update();
+        }
+        assert_eq!(loaded.starts, world.starts);
+        for (a, b) in loaded.bots.iter().zip(world.bots.iter()) {
+            assert_eq!(a.network.dump(), b.network.dump());
+        }
+
+        // resumed on points, the hall is rescored before new candidates join
+        let mut rescored = World::new(Config {
+            fitness: Fitness::Score,
+            ..config.clone()
+        })
+        .unwrap();
+        let objective = rescored.config.objective();
+        assert_ne!(rescored.hall.objective, Some(objective));
+        rescored.update_hall(2);
+        assert_eq!(rescored.hall.objective, Some(objective));
+        for e in rescored.hall.entries.iter() {
+            assert_eq!(e.fitness, rescored.hall.validate(&e.network, objective, 1));
+        }
+
+        // a corrupt latest checkpoint falls back to the previous one, here
+        // the save of the other run
+        std::fs::write(&config.path, b"garbage").unwrap();
+        let previous = World::new(config).unwrap();
+        assert_eq!(previous.generation, 2);
+        assert_eq!(previous.starts, world.starts);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn deterministic() {
+        let mut config = Config {
+            depth: 1,
+            seed: Some(2),
+            seeds_per_generation: 2,
+            hall_size: 0,
+            ..Config::default()
+        };
+        config.set_population(16).unwrap();
+        let runs: Vec<Vec<(f64, Vec<f32>)>> = [1, 3, 16]
+            .into_iter()
+            .map(|workers| {
+                let mut world = World::random(config.clone());
+                world.run(workers);
+                world
+                    .bots
+                    .iter()
+                    .map(|b| (b.fitness, b.network.dump()))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(runs[0], runs[1]);
+        assert_eq!(runs[0], runs[2]);
+    }
+
+    #[test]
+    fn dump_load() {
+        let mut world = World::random(Config::default());
+        world.generation = 3;
+        let loaded = World::load(&world.dump(), Config::default()).unwrap();
+        assert_eq!(loaded.generation, 3);
+        assert_eq!(loaded.bots[0].network.dump(), world.bots[0].network.dump());
+
+        let mut population = world.to_population();
+        population.bots.pop();
+        assert!(matches!(
+            World::load(&population.encode(), Config::default()),
+            Err(FormatError::Population { .. })
+        ));
+    }
+
+    #[test]
+    fn diversity() {
+        let dir = std::env::temp_dir().join(format!("2048ai-diversity-{}", std::process::id()));
+        let mut config = Config {
+            depth: 1,
+            seed: Some(4),
+            hall_size: 0,
+            niching: Niching::Species { threshold: 0.1 },
+            novelty: 0.5,
+            novelty_archive: 1,
+            path: dir.join("population.bin"),
+            ..Config::default()
+        };
+        config.set_population(8).unwrap();
+        let mut world = World::random(config.clone());
+        let record = world.run(2);
+        // random networks are all far apart
+        assert_eq!(record.species, 8);
+        assert!(record.distance > 0.5);
+        assert!(record.novelty > 0.0);
+        assert_eq!(world.scores.len(), 8);
+        assert!(world.scores.iter().all(|s| (0.0..=1.0).contains(s)));
+        assert_eq!(world.archive.len(), 1);
+        world.update();
+        assert!(config.path.exists());
+        world.run(2);
+        assert_eq!(world.archive.len(), 1);
+
+        let loaded = World::load(&world.dump(), config).unwrap();
+        assert_eq!(loaded.archive, world.archive);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn curriculum() {
+        let dir = std::env::temp_dir().join(format!("2048ai-curriculum-{}", std::process::id()));
+        let mut config = Config {
+            depth: 1,
+            seed: Some(6),
+            hall_size: 0,
+            seeds_per_generation: 3,
+            curriculum: Schedule::Fixed(1.0),
+            curriculum_min_tile: 3,
+            path: dir.join("population.bin"),
+            ..Config::default()
+        };
+        config.set_population(4).unwrap();
+        let mut world = World::random(config.clone());
+        // nothing archived yet
+        assert!(world.pool.boards.is_empty());
+        assert!(world.starts.iter().all(|b| b.data.max_tile() <= 2));
+        world.run(2);
+        assert!(!world.pool.boards.is_empty());
+        world.update();
+        assert!(world.starts.iter().all(|b| world.pool.boards.contains(b)));
+        assert!(config.path.exists());
+
+        let loaded = World::load(&world.dump(), config).unwrap();
+        assert_eq!(loaded.pool, world.pool);
+        assert_eq!(loaded.starts, world.starts);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cma() {
+        let config = Config {
+            optimizer: Optimizer::CmaEs,
+            cma_population: 8,
+            ..Config::default()
+        };
+        let mut world = World::random(config.clone());
+        assert_eq!(world.bots.len(), 8);
+        let loaded = World::load(&world.dump(), config.clone()).unwrap();
+        assert_eq!(loaded.cma.unwrap().mean, world.cma.as_ref().unwrap().mean);
+
+        let bots = &mut world.bots;
+        for (i, bot) in bots.iter_mut().enumerate() {
+            bot.fitness = i as f64;
+        }
+        bots.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+        let ranked: Vec<Network> = world.bots.iter().map(|b| b.network).collect();
+        world.cma.as_mut().unwrap().tell(&ranked);
+        assert_eq!(world.cma.as_ref().unwrap().generation, 1);
+
+        // a genetic population is turned into a distribution around its best bot
+        let genetic = World::random(Config::default());
+        let switched = World::load(&genetic.dump(), config).unwrap();
+        assert_eq!(switched.bots.len(), 8);
+        assert!(switched.cma.is_some());
+    }
+
+    #[test]
+    fn operators() {
+        let config = Config {
+            elitism: 3,
+            selection: Selection::Tournament(3),
+            mutation: Mutation::SelfAdaptive {
+                rate: 0.05,
+                sigma: 0.1,
+            },
+            crossover: Crossover::Sbx(15.0),
+            ..Config::default()
+        };
+        let mut world = World::random(config.clone());
+        let bots = &mut world.bots;
+        for (i, bot) in bots.iter_mut().enumerate() {
+            bot.fitness = (NUM_BOTS - i) as f64;
+        }
+        let next = world.breed();
+        assert_eq!(next.len(), NUM_BOTS);
+        let elites = &next[DO_MUTATION + DO_CROSS * 2..][..3];
+        for (elite, bot) in elites.iter().zip(world.bots.iter()) {
+            assert_eq!(elite.network.dump(), bot.network.dump());
+        }
+        assert!(next[0].step > 0.0);
+
+        world.bots = next;
+        let population = world.to_population();
+        let meta = |key: &str| {
+            population
+                .metadata
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        };
+        assert_eq!(meta("selection").as_deref(), Some("tournament:3"));
+        assert_eq!(meta("crossover").as_deref(), Some("sbx:15"));
+        assert_eq!(meta("elitism").as_deref(), Some("3"));
+        let loaded = World::load(&population.encode(), config).unwrap();
+        assert_eq!(loaded.bots[0].step, world.bots[0].step);
     }
 }
@@ -1,4 +1,4 @@
-#[macro_export] // python:del
+#[macro_export]
 macro_rules! include_data {
     ($size:expr,$bytes:expr) => {{
         const R: usize = (0xbf - 0x80 + 1);
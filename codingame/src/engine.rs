@@ -1,148 +1,120 @@
-use std::arch::x86_64::_pext_u64; //python:del
-use std::fmt; //python:del
-use std::mem; //python:del
-use std::mem::transmute; //python:del
+use std::arch::x86_64::_pext_u64;
+use std::fmt;
+use std::mem::transmute;
 
-use crate::network::T8; //python:del
+use crate::network::T8;
 
-fn u16_to_data(d: u16) -> [i32; 4] {
-    let mut data = [0; 4];
-    for i in 0..4 {
-        data[i] = ((d >> (i * 4)) & 0xf) as i32;
-    }
-    data
-}
-
-fn data_to_u16(data: &[i32; 4]) -> u16 {
-    let mut d = 0;
-    for i in 0..4 {
-        d |= (data[i] as u16) << (i * 4);
-    }
-    d
-}
-
-fn data_to_u64(data: &[i32; 4]) -> u64 {
-    let mut d = 0;
-    for i in 0..4 {
-        d |= (data[i] as u64) << (i * 16);
-    }
-    d
-}
-
-fn calc_line_l(data: &[i32; 4]) -> [i32; 4] {
-    let mut res = [0; 4];
-    let mut index = 0;
-    let mut flag = false;
-    for num in data.iter() {
-        if *num != 0 {
-            if flag && res[index - 1] == *num {
-                if res[index - 1] != 15 {
-                    res[index - 1] += 1;
+/// A line after a move towards its low bits, the first tile being the
+/// lowest nibble. Tiles stop at 15.
+const fn move_line(line: u16) -> u16 {
+    let mut res = 0;
+    let mut shift = 0;
+    let mut last = 0;
+    let mut i = 0;
+    while i < 16 {
+        let tile = (line >> i) & 0xf;
+        if tile != 0 {
+            if tile == last {
+                if tile != 15 {
+                    res += 1 << (shift - 4);
                 }
-                flag = false;
+                last = 0;
             } else {
-                res[index] = *num;
-                index += 1;
-                flag = true;
+                res |= tile << shift;
+                shift += 4;
+                last = tile;
             }
         }
+        i += 4;
     }
     res
 }
 
-fn calc_line_r(data: &[i32; 4]) -> [i32; 4] {
-    let mut res = [0; 4];
-    let mut index = 4;
-    let mut flag = false;
-    for num in data.iter().rev() {
-        if *num != 0 {
-            if flag && res[index] == *num {
-                if res[index] != 15 {
-                    res[index] += 1;
-                }
-                flag = false;
-            } else {
-                res[index - 1] = *num;
-                index -= 1;
-                flag = true;
-            }
-        }
-    }
-    res
+const fn reverse(line: u16) -> u16 {
+    (line >> 12) | ((line >> 4) & 0xf0) | ((line << 4) & 0xf00) | (line << 12)
+}
+
+/// A row as a column: tile `i` goes to bits `16 * i`.
+const fn to_col(line: u16) -> u64 {
+    let line = line as u64;
+    (line & 0xf) | ((line & 0xf0) << 12) | ((line & 0xf00) << 24) | ((line & 0xf000) << 36)
+}
+
+const fn free(line: u16) -> usize {
+    let tiles = line | (line >> 1) | (line >> 2) | (line >> 3);
+    4 - (tiles & 0x1111).count_ones() as usize
 }
 
+#[derive(Clone, Copy)]
 struct RowData {
     right: u16,
     left: u16,
     free: usize,
 }
 
-impl RowData {
-    fn new(data_r: &[i32; 4], data_l: &[i32; 4]) -> Self {
-        RowData {
-            right: data_to_u16(data_r),
-            left: data_to_u16(data_l),
-            free: data_r.iter().filter(|&x| *x == 0).count(),
-        }
-    }
-}
-
+#[derive(Clone, Copy)]
 struct ColData {
     up: u64,
     down: u64,
     free: usize,
 }
 
-impl ColData {
-    fn new(data_u: &[i32; 4], data_d: &[i32; 4]) -> Self {
-        ColData {
-            up: data_to_u64(data_u),
-            down: data_to_u64(data_d),
-            free: data_u.iter().filter(|&x| *x == 0).count(),
-        }
+/// Moves of every line, indexed by its 16 bits. Built by the compiler.
+#[allow(long_running_const_eval)]
+static TABLE: ([RowData; 65536], [ColData; 65536]) = calc_table();
+
+const fn calc_table() -> ([RowData; 65536], [ColData; 65536]) {
+    // each line moved once, a move to the right is the reversed line moved
+    let mut lefts = [0u16; 65536];
+    let mut i = 0;
+    while i < 65536 {
+        lefts[i] = move_line(i as u16);
+        i += 1;
+    }
+    let mut row_data = [RowData {
+        right: 0,
+        left: 0,
+        free: 0,
+    }; 65536];
+    let mut col_data = [ColData {
+        up: 0,
+        down: 0,
+        free: 0,
+    }; 65536];
+    let mut i = 0;
+    while i < 65536 {
+        let left = lefts[i];
+        let right = reverse(lefts[reverse(i as u16) as usize]);
+        let free = free(left);
+        row_data[i] = RowData { right, left, free };
+        col_data[i] = ColData {
+            up: to_col(left),
+            down: to_col(right),
+            free,
+        };
+        i += 1;
     }
+    (row_data, col_data)
 }
 
-fn calc_line(data: &[i32; 4]) -> (RowData, ColData) {
-    let data_l = calc_line_l(data);
-    let data_r = calc_line_r(data);
-    (
-        RowData::new(&data_r, &data_l),
-        ColData::new(&data_l, &data_r),
-    )
-}
+/// Network input of every row, indexed by its 16 bits.
+#[allow(long_running_const_eval)]
+static F_TABLE: [[f32; 4]; 65536] = calc_f_table();
 
-static mut ROW_TABLE: Vec<RowData> = unsafe { transmute([1u8; 24]) };
-static mut COL_TABLE: Vec<ColData> = unsafe { transmute([1u8; 24]) };
-static mut F_TABLE: Vec<[f32; 4]> = unsafe { transmute([1u8; 24]) };
-fn calc_table() {
-    let mut row_data: Vec<RowData> = Vec::with_capacity(65536);
-    let mut col_data: Vec<ColData> = Vec::with_capacity(65536);
-    for i in 0..=65535u16 {
-        let data = u16_to_data(i);
-        let (row, col) = calc_line(&data);
-        row_data.push(row);
-        col_data.push(col);
+const fn calc_f_table() -> [[f32; 4]; 65536] {
+    let mut f = [0f32; 16];
+    let mut i = 0;
+    while i < 16 {
+        f[i] = i as f32 / 15f32;
+        i += 1;
     }
-    unsafe {
-        mem::swap(&mut row_data, &mut ROW_TABLE);
-        mem::swap(&mut col_data, &mut COL_TABLE);
-        mem::forget(row_data);
-        mem::forget(col_data);
-    }
-    let mut f_table = Vec::with_capacity(65536);
-    for i in 0..=65535u16 {
-        let data = u16_to_data(i);
-        let mut f = [0f32; 4];
-        for j in 0..4 {
-            f[j] = data[j] as f32 / 15f32;
-        }
-        f_table.push(f);
-    }
-    unsafe {
-        mem::swap(&mut f_table, &mut F_TABLE);
-        mem::forget(f_table);
+    let mut f_table = [[0f32; 4]; 65536];
+    let mut i = 0;
+    while i < 65536 {
+        f_table[i] = [f[i & 0xf], f[(i >> 4) & 0xf], f[(i >> 8) & 0xf], f[i >> 12]];
+        i += 1;
     }
+    f_table
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -156,7 +128,7 @@ impl fmt::Display for Data {
         }
         for (i, data) in data.iter().enumerate() {
             if i % 4 == 0 {
-                write!(f, "\n")?;
+                writeln!(f)?;
             }
             if *data != 0 {
                 write!(f, "{:8}", 2i32.pow(*data as u32))?;
@@ -168,32 +140,13 @@ impl fmt::Display for Data {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-struct Seed {
-    seed: usize,
-    value: u8,
-}
-static mut SEED_TABLE: Vec<Seed> = unsafe { transmute([1u8; 24]) };
-
-fn set_seed(mut seed: u64) {
-    let count = 100_000;
-    let mut seed_table: Vec<Seed> = Vec::with_capacity(count);
-    for _ in 0..count {
-        let seed_data = Seed {
-            seed: seed as usize,
-            value: if seed & 0x10 == 0 { 1 } else { 2 },
-        };
-        seed_table.push(seed_data);
-        seed = seed * seed % 50515093;
-    }
-    unsafe {
-        mem::swap(&mut seed_table, &mut SEED_TABLE);
-        mem::forget(seed_table);
-    }
-}
+/// Spawn seeds follow `seed * seed % SEED_MOD`.
+pub const SEED_MOD: u64 = 50515093;
 
 pub struct Board {
     pub turn: usize,
+    /// Seed of the next spawn.
+    pub seed: u64,
     pub data: Data,
 }
 
@@ -207,13 +160,66 @@ pub struct Moves {
     pub free_ud: usize,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    /// Every direction, in the order the search prefers on ties.
+    pub const ALL: [Direction; 4] = [
+        Direction::Down,
+        Direction::Right,
+        Direction::Up,
+        Direction::Left,
+    ];
+
+    #[allow(dead_code)]
+    pub fn to_char(self) -> char {
+        match self {
+            Direction::Up => 'U',
+            Direction::Down => 'D',
+            Direction::Left => 'L',
+            Direction::Right => 'R',
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'U' => Some(Direction::Up),
+            'D' => Some(Direction::Down),
+            'L' => Some(Direction::Left),
+            'R' => Some(Direction::Right),
+            _ => None,
+        }
+    }
+}
+
+impl Moves {
+    /// Tiles and free cells after moving `dir`.
+    pub fn get(&self, dir: Direction) -> (Data, usize) {
+        match dir {
+            Direction::Up => (self.up, self.free_ud),
+            Direction::Down => (self.down, self.free_ud),
+            Direction::Left => (self.left, self.free_rl),
+            Direction::Right => (self.right, self.free_rl),
+        }
+    }
+}
+
 const COL_MASK: u64 = 15 | (15 << 16) | (15 << 32) | (15 << 48);
 const COL_MASKS: [u64; 4] = [COL_MASK, COL_MASK << 4, COL_MASK << 8, COL_MASK << 12];
 
 impl Board {
-    pub fn new() -> Self {
+    #[allow(dead_code)]
+    pub fn new(seed: u64) -> Self {
         let s0 = Self {
             turn: 0,
+            seed: seed % SEED_MOD,
             data: Data(0),
         };
         let s1 = s0.spawn(Data(0), 16);
@@ -232,8 +238,8 @@ impl Board {
         for i in 0..4 {
             let row = (self.data.0 >> (i * 16)) & 0xffff;
             let col = _pext_u64(self.data.0, COL_MASKS[i]);
-            let row_data = ROW_TABLE.get_unchecked(row as usize);
-            let col_data = COL_TABLE.get_unchecked(col as usize);
+            let row_data = TABLE.0.get_unchecked(row as usize);
+            let col_data = TABLE.1.get_unchecked(col as usize);
             right |= (row_data.right as u64) << (i * 16);
             left |= (row_data.left as u64) << (i * 16);
             up |= col_data.up << (i * 4);
@@ -253,8 +259,8 @@ impl Board {
     }
 
     pub fn spawn(&self, data: Data, free: usize) -> Self {
-        let seed = unsafe { SEED_TABLE.get_unchecked(self.turn) };
-        let index = seed.seed % free;
+        let index = (self.seed % free as u64) as usize;
+        let value = if self.seed & 0x10 == 0 { 1u64 } else { 2 };
         let mask = 0xf;
         let mut pos = 0;
         for x in 0..4 {
@@ -266,7 +272,8 @@ impl Board {
                     if pos == index {
                         return Self {
                             turn: self.turn + 1,
-                            data: Data(data.0 | ((seed.value as u64) << shift)),
+                            seed: self.seed * self.seed % SEED_MOD,
+                            data: Data(data.0 | (value << shift)),
                         };
                     }
                     pos += 1;
@@ -276,30 +283,139 @@ impl Board {
         panic!("{} {}", index, pos);
     }
 
+    /// The board after moving `dir` and its spawn, `None` if the move
+    /// changes nothing.
+    #[allow(dead_code)]
+    pub fn apply(&self, dir: Direction) -> Option<Board> {
+        let (data, free) = unsafe { self.moves() }.get(dir);
+        (data != self.data).then(|| self.spawn(data, free))
+    }
+
+    /// Every move that changes the board, in the order of `Direction::ALL`,
+    /// with the board after it.
+    pub fn successors(&self) -> impl Iterator<Item = (Direction, Board)> + '_ {
+        let moves = unsafe { self.moves() };
+        Direction::ALL.into_iter().filter_map(move |dir| {
+            let (data, free) = moves.get(dir);
+            (data != self.data).then(|| (dir, self.spawn(data, free)))
+        })
+    }
+
     pub fn to_t8(&self) -> [T8; 2] {
         unsafe {
             let t1 = [
-                F_TABLE
-                    .get_unchecked(((self.data.0 >> 0) & 0xffff) as usize)
-                    .clone(),
-                F_TABLE
-                    .get_unchecked(((self.data.0 >> 16) & 0xffff) as usize)
-                    .clone(),
+                *F_TABLE.get_unchecked((self.data.0 & 0xffff) as usize),
+                *F_TABLE.get_unchecked(((self.data.0 >> 16) & 0xffff) as usize),
             ];
             let t2 = [
-                F_TABLE
-                    .get_unchecked(((self.data.0 >> 32) & 0xffff) as usize)
-                    .clone(),
-                F_TABLE
-                    .get_unchecked(((self.data.0 >> 48) & 0xffff) as usize)
-                    .clone(),
+                *F_TABLE.get_unchecked(((self.data.0 >> 32) & 0xffff) as usize),
+                *F_TABLE.get_unchecked(((self.data.0 >> 48) & 0xffff) as usize),
             ];
             [T8::new(transmute(t1)), T8::new(transmute(t2))]
         }
     }
 }
 
-pub fn set_up(seed: u64) {
-    calc_table();
-    set_seed(seed);
+impl Data {
+    /// Points of the merges that built the tiles if every tile grew from 2s:
+    /// `(k - 1) * 2^k` for a tile `2^k`.
+    pub fn potential(&self) -> u64 {
+        (0..16)
+            .map(|i| (self.0 >> (i * 4)) & 0xf)
+            .filter(|k| *k > 0)
+            .map(|k| (k - 1) << k)
+            .sum()
+    }
+}
+
+impl Board {
+    /// Points scored by the move from `self` to `next`, its successor.
+    /// Tiles stop at 32768: two of them merge into one 32768, and that
+    /// merge scores nothing instead of the 65536 of the reference, see
+    /// `Board::score` for what it means to the search.
+    #[allow(dead_code)]
+    pub fn points(&self, next: &Board) -> u64 {
+        let four = if self.seed & 0x10 != 0 { 4 } else { 0 };
+        next.data
+            .potential()
+            .saturating_sub(self.data.potential() + four)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A trace board as the engine holds it: the 65536 (`g`) of two merged
+    /// 32768s stays a 32768.
+    fn parse(board: &str) -> Data {
+        let tiles = board.chars().filter(|c| *c != '/');
+        let tiles = tiles.map(|c| c.to_digit(17).unwrap().min(15) as u64);
+        Data(tiles.enumerate().map(|(i, t)| t << (i * 4)).sum())
+    }
+
+    fn apply(board: &Board, dir: &str) -> Option<Board> {
+        let dir = Direction::from_char(dir.chars().next().unwrap()).unwrap();
+        let next = board.apply(dir);
+        let successor = board.successors().find(|(d, _)| *d == dir);
+        assert_eq!(
+            next.as_ref().map(|b| b.data),
+            successor.map(|(_, b)| b.data)
+        );
+        next
+    }
+
+    #[test]
+    fn golden() {
+        let traces = include_str!("../../golden/traces.txt");
+        let mut game = None;
+        for (i, line) in traces.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [] => {}
+                ["#", ..] => {}
+                ["new", seed, next, board] => {
+                    let board_new = Board::new(seed.parse().unwrap());
+                    assert_eq!(board_new.data, parse(board), "line {}", i + 1);
+                    assert_eq!(
+                        board_new.seed,
+                        next.parse::<u64>().unwrap(),
+                        "line {}",
+                        i + 1
+                    );
+                    game = Some((board_new, 0));
+                }
+                ["game", seed, board] => {
+                    let board = Board {
+                        turn: 0,
+                        seed: seed.parse().unwrap(),
+                        data: parse(board),
+                    };
+                    game = Some((board, 0));
+                }
+                ["over"] => {
+                    let (board, _) = game.as_ref().unwrap();
+                    assert_eq!(board.successors().count(), 0, "line {}", i + 1);
+                }
+                [dir, seed, score, board] => {
+                    let (board_before, points) = game.as_mut().unwrap();
+                    let next = apply(board_before, dir).unwrap();
+                    *points += board_before.points(&next);
+                    assert_eq!(next.data, parse(board), "line {}", i + 1);
+                    assert_eq!(next.seed, seed.parse::<u64>().unwrap(), "line {}", i + 1);
+                    // the merge into 65536 scores nothing, see `Board::points`
+                    let lost = if board.contains('g') { 65536 } else { 0 };
+                    assert_eq!(
+                        *points + lost,
+                        score.parse::<u64>().unwrap(),
+                        "line {}",
+                        i + 1
+                    );
+                    *board_before = next;
+                }
+                _ => panic!("line {}: {:?}", i + 1, line),
+            }
+        }
+        assert!(game.is_some());
+    }
 }
@@ -1,34 +1,174 @@
-use crate::engine::set_up; //python:del
-use crate::engine::Board; //python:del
-use crate::engine::Data; //python:del
-use std::io; //python:del
+use crate::engine::{Board, Data, SEED_MOD};
+use std::fmt;
+use std::io;
+use std::io::BufRead;
 
-#[allow(dead_code)]
-impl Board {
-    fn from_input() -> Self {
-        let mut buf = String::new();
-        std::io::stdin().read_line(&mut buf).unwrap();
-        let seed = buf.trim().parse::<u64>().unwrap();
-        std::io::stdin().read_line(&mut buf).unwrap();
+/// Input of one turn: the seed of the next spawn, the score and the grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Turn {
+    pub seed: u64,
+    pub score: u64,
+    pub data: Data,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InputError {
+    /// Line of the turn, from 1 to 6.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "input line {}: {}", self.line, self.message)
+    }
+}
+
+fn error(line: usize, message: String) -> InputError {
+    InputError { line, message }
+}
+
+fn read_line<R: BufRead>(input: &mut R, line: usize) -> Result<String, InputError> {
+    let mut buf = String::new();
+    match input.read_line(&mut buf) {
+        Ok(0) => Err(error(line, "unexpected end of input".to_string())),
+        Ok(_) => Ok(buf),
+        Err(e) => Err(error(line, e.to_string())),
+    }
+}
+
+/// Exponent of a tile value: 0 for an empty cell, `k` for `2^k`.
+fn tile(value: &str) -> Option<u64> {
+    let value = value.parse::<u64>().ok()?;
+    match value {
+        0 => Some(0),
+        v if v.is_power_of_two() && (2..=32768).contains(&v) => Some(v.trailing_zeros() as u64),
+        _ => None,
+    }
+}
+
+impl Turn {
+    pub fn read<R: BufRead>(input: &mut R) -> Result<Self, InputError> {
+        let number = |line: usize, what: &str, text: String| {
+            text.trim()
+                .parse::<u64>()
+                .map_err(|_| error(line, format!("invalid {} {:?}", what, text.trim())))
+        };
+        let seed = number(1, "seed", read_line(input, 1)?)?;
+        let score = number(2, "score", read_line(input, 2)?)?;
         let mut data = 0u64;
-        for i in 0..4 as usize {
-            buf.clear();
-            io::stdin().read_line(&mut buf).unwrap();
-            for (j, val) in buf.split_whitespace().enumerate() {
-                let cell = val.trim().parse::<u64>().unwrap();
-                let cell = match cell {
-                    0 => 0u64,
-                    2 => 1,
-                    4 => 2,
-                    _ => panic!("init errr"),
-                };
-                data = data | ((cell as u64) << (4 * (i * 4 + j)));
+        for i in 0..4usize {
+            let line = i + 3;
+            let row = read_line(input, line)?;
+            let cells: Vec<&str> = row.split_whitespace().collect();
+            if cells.len() != 4 {
+                return Err(error(
+                    line,
+                    format!("expected 4 tiles, got {:?}", row.trim()),
+                ));
+            }
+            for (j, val) in cells.iter().enumerate() {
+                let cell =
+                    tile(val).ok_or_else(|| error(line, format!("invalid tile {:?}", val)))?;
+                data |= cell << (4 * (i * 4 + j));
             }
         }
-        set_up(seed);
-        Board {
+        Ok(Turn {
+            seed,
+            score,
             data: Data(data),
+        })
+    }
+}
+
+#[allow(dead_code)]
+impl Board {
+    /// Reads the first turn. Also returns the score.
+    pub fn from_input() -> Result<(Self, u64), InputError> {
+        let turn = Turn::read(&mut io::stdin().lock())?;
+        Ok((Board::from_turn(&turn), turn.score))
+    }
+
+    /// The board of a turn.
+    pub fn from_turn(turn: &Turn) -> Self {
+        Board {
+            data: turn.data,
+            seed: turn.seed % SEED_MOD,
             turn: 0,
         }
     }
+
+    /// Compares the simulated board and score with the input of a turn.
+    /// Returns what differs.
+    pub fn verify(&self, score: u64, turn: &Turn) -> Option<String> {
+        let mut diffs = vec![];
+        if self.seed != turn.seed {
+            diffs.push(format!("seed {} instead of {}", turn.seed, self.seed));
+        }
+        if score != turn.score {
+            diffs.push(format!("score {} instead of {}", turn.score, score));
+        }
+        if self.data != turn.data {
+            diffs.push(format!("grid\n{}\ninstead of\n{}", turn.data, self.data));
+        }
+        (!diffs.is_empty()).then(|| diffs.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let input = "1234\n56\n0 2 4 8\n0 0 0 0\n32768 0 0 0\n0 0 0 2\n";
+        let turn = Turn::read(&mut input.as_bytes()).unwrap();
+        assert_eq!(turn.seed, 1234);
+        assert_eq!(turn.score, 56);
+        assert_eq!(turn.data, Data(0x3210 | (15 << 32) | (1 << 60)));
+
+        let err = |input: &str| Turn::read(&mut input.as_bytes()).unwrap_err().to_string();
+        assert_eq!(err("x\n"), "input line 1: invalid seed \"x\"");
+        assert_eq!(
+            err("1\n2\n0 0 0\n"),
+            "input line 3: expected 4 tiles, got \"0 0 0\""
+        );
+        assert_eq!(
+            err("1\n2\n0 0 0 0\n0 3 0 0\n"),
+            "input line 4: invalid tile \"3\""
+        );
+        assert_eq!(
+            err("1\n2\n0 0 0 0\n"),
+            "input line 4: unexpected end of input"
+        );
+    }
+
+    #[test]
+    fn verify() {
+        let board = Board::new(290797);
+        let turn = Turn {
+            seed: board.seed,
+            score: 0,
+            data: board.data,
+        };
+        assert_eq!(board.verify(0, &turn), None);
+        let desync = board.verify(4, &Turn { seed: 1, ..turn }).unwrap();
+        assert!(desync.starts_with("seed 1 instead of"));
+        assert!(desync.ends_with("score 0 instead of 4"));
+
+        // points follow the merges
+        let mut board = board;
+        let mut score = 0;
+        while let Some((_, next)) = board.ai(1) {
+            score += board.points(&next);
+            board = next;
+        }
+        assert!(score > 0);
+        let potential: u64 = (0..16)
+            .map(|i| (board.data.0 >> (i * 4)) & 0xf)
+            .filter(|k| *k > 0)
+            .map(|k| (k - 1) << k)
+            .sum();
+        assert!(score <= potential && potential - score <= 4 * board.turn as u64);
+    }
 }
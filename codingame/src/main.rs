@@ -1,5 +1,10 @@
+#![allow(
+    clippy::needless_range_loop,
+    clippy::missing_transmute_annotations
+)]
+
 use crate::{
-    engine::{set_up, Board},
+    engine::Board,
     network::load_network,
 };
 
@@ -11,20 +16,21 @@ mod input;
 mod network;
 mod score;
 mod timer;
+mod weights;
 
 fn main() {
     let start = std::time::Instant::now();
-    set_up(290797);
+    if weights::ORIGIN.is_none() {
+        println!("placeholder weights");
+    }
     load_network();
-    let mut board = Board::new();
+    let mut board = Board::new(290797);
     let mut count = 0;
     loop {
         count += 1;
-        let m = board.auto_ai();
-        if let Some(b) = m.1 {
-            board = b;
-        } else {
-            break;
+        match board.auto_ai() {
+            Some((_, b)) => board = b,
+            None => break,
         }
         println!("Count: {}", count);
     }
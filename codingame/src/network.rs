@@ -1,6 +1,8 @@
-use std::arch::x86_64::*; //python:del
-use std::mem::transmute; //python:del
-use std::ops; //python:del
+use crate::weights::{WEIGHTS, WEIGHTS_SIZE};
+use std::arch::x86_64::*;
+use std::mem::transmute;
+use std::ops;
+use std::sync::OnceLock;
 
 const ZERO: __m256 = unsafe { transmute([0f32; 8]) };
 
@@ -35,7 +37,7 @@ impl T8 {
 
     #[target_feature(enable = "avx2")]
     unsafe fn _add(&self, other: &Self) -> Self {
-        T8(unsafe { _mm256_add_ps(self.0, other.0) })
+        T8(_mm256_add_ps(self.0, other.0))
     }
 
     #[target_feature(enable = "avx2")]
@@ -45,7 +47,7 @@ impl T8 {
 
     #[target_feature(enable = "avx2")]
     unsafe fn _mul(&self, other: &Self) -> Self {
-        T8(unsafe { _mm256_mul_ps(self.0, other.0) })
+        T8(_mm256_mul_ps(self.0, other.0))
     }
 
     #[target_feature(enable = "avx2")]
@@ -228,12 +230,17 @@ network!(Network={
     b : Layer4_4
 });
 
-pub static mut NETWORK: Network = unsafe { transmute([0u8; Network::size() * 4]) };
+static NETWORK: OnceLock<Network> = OnceLock::new();
+// weights.rs is written by `ai pack` for this topology
+const _: () = assert!(WEIGHTS_SIZE == Network::size() * 4);
+const DATA: [f32; Network::size()] = include_data!(WEIGHTS_SIZE, WEIGHTS);
+
+/// The network of `weights.rs`, loaded on first use.
+pub fn network() -> &'static Network {
+    NETWORK.get_or_init(|| Network::load(&DATA))
+}
+
+/// Loads the network before the clock of the first turn matters.
 pub fn load_network() {
-    let data = [0f32]; //TODO: load from file
-    let mut net = Network::load(&data);
-    unsafe {
-        std::mem::swap(&mut NETWORK, &mut net);
-        std::mem::forget(net);
-    }
+    network();
 }
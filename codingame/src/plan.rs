@@ -0,0 +1,185 @@
+use crate::engine::{Board, Direction};
+use crate::timer::TimeManager;
+use std::time::{Duration, Instant};
+
+/// Depth moves far ahead are planned at.
+const DEPTH: usize = 5;
+/// Depth of the moves about to be sent.
+const REFINE_DEPTH: usize = 6;
+/// Planned moves held back from each answer, refined in the next turns.
+const RESERVE: usize = 32;
+
+struct Step {
+    dir: Direction,
+    board: Board,
+    depth: usize,
+}
+
+/// Plans the game ahead of the moves sent so far. The game is deterministic
+/// once the seed is known, so the plan stays valid until a deeper search
+/// prefers another move, which drops the rest of the plan.
+///
+/// The first `RESERVE` planned moves are searched at `REFINE_DEPTH`, the
+/// rest at `DEPTH` until they get close to being sent. A search only starts
+/// if it should end well before the turn does.
+pub struct Planner {
+    /// Position after the moves sent so far.
+    pub board: Board,
+    pub score: u64,
+    plan: Vec<Step>,
+    /// Whether the plan runs to the end of the game.
+    over: bool,
+    /// Recent cost of a search at `DEPTH` and at `REFINE_DEPTH`.
+    cost: [Duration; 2],
+}
+
+impl Planner {
+    pub fn new(board: Board, score: u64) -> Self {
+        Planner {
+            board,
+            score,
+            plan: vec![],
+            over: false,
+            cost: [Duration::ZERO; 2],
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.over && self.plan.is_empty()
+    }
+
+    fn before(&self, i: usize) -> &Board {
+        if i == 0 {
+            &self.board
+        } else {
+            &self.plan[i - 1].board
+        }
+    }
+
+    /// The next search to run, as the index of the planned move and the
+    /// depth: the first move about to be sent that is not at `REFINE_DEPTH`
+    /// yet, else a move after the plan.
+    fn next_search(&self) -> Option<(usize, usize)> {
+        let window = self.plan.len().min(RESERVE);
+        match self.plan[..window]
+            .iter()
+            .position(|s| s.depth < REFINE_DEPTH)
+        {
+            Some(i) => Some((i, REFINE_DEPTH)),
+            None if self.over => None,
+            None if self.plan.len() < RESERVE => Some((self.plan.len(), REFINE_DEPTH)),
+            None => Some((self.plan.len(), DEPTH)),
+        }
+    }
+
+    fn cost(&mut self, depth: usize) -> &mut Duration {
+        &mut self.cost[(depth == REFINE_DEPTH) as usize]
+    }
+
+    /// Searches move `i` of the plan at `depth`, repairing the plan if an
+    /// existing move changes.
+    fn search(&mut self, i: usize, depth: usize) {
+        let start = Instant::now();
+        let best = self.before(i).ai(depth);
+        let elapsed = start.elapsed();
+        let cost = self.cost(depth);
+        *cost = elapsed.max(*cost * 3 / 4);
+        let Some((dir, board)) = best else {
+            self.over = true;
+            return;
+        };
+        if i < self.plan.len() && self.plan[i].dir == dir {
+            self.plan[i].depth = depth;
+            return;
+        }
+        self.plan.truncate(i);
+        self.over = false;
+        self.plan.push(Step { dir, board, depth });
+    }
+
+    /// Works on the plan while the turn lasts. Plans at least one move.
+    pub fn think(&mut self, timer: &TimeManager) {
+        while let Some((i, depth)) = self.next_search() {
+            // twice the recent cost, searches near the end of a game vary
+            if *self.cost(depth) * 2 >= timer.left() {
+                break;
+            }
+            self.search(i, depth);
+        }
+        if self.plan.is_empty() && !self.over {
+            self.search(0, DEPTH);
+        }
+    }
+
+    /// Takes the moves to send: all but the last `RESERVE` planned ones,
+    /// at least one, or all of them once the plan reaches the end of the
+    /// game. `None` when `board` has no move left, there is nothing legal to
+    /// send then.
+    pub fn commit(&mut self) -> Option<String> {
+        if self.plan.is_empty() {
+            return None;
+        }
+        let n = if self.over {
+            self.plan.len()
+        } else {
+            self.plan
+                .len()
+                .saturating_sub(RESERVE)
+                .max(1)
+                .min(self.plan.len())
+        };
+        let mut moves = String::with_capacity(n);
+        for step in self.plan.drain(..n) {
+            self.score += self.board.points(&step.board);
+            self.board = step.board;
+            moves.push(step.dir.to_char());
+        }
+        Some(moves)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::Data;
+    use crate::network::load_network;
+
+    #[test]
+    fn plan() {
+        load_network();
+        let mut planner = Planner::new(Board::new(290797), 0);
+        while planner.plan.len() < 2 * RESERVE {
+            let (i, depth) = planner.next_search().unwrap();
+            assert_eq!(depth == DEPTH, i >= RESERVE);
+            planner.search(i, depth);
+        }
+        assert!(planner.plan[..RESERVE]
+            .iter()
+            .all(|s| s.depth == REFINE_DEPTH));
+        assert!(planner.plan[RESERVE..].iter().all(|s| s.depth == DEPTH));
+        assert!(planner.cost[1] > Duration::ZERO);
+
+        let planned: String = planner.plan.iter().map(|s| s.dir.to_char()).collect();
+        let moves = planner.commit().unwrap();
+        assert_eq!(moves.len(), RESERVE);
+        assert!(planned.starts_with(&moves));
+        assert_eq!(planner.board.turn, 2 + moves.len());
+        assert!(planner.score > 0);
+        // the moves planned at `DEPTH` are about to be sent now
+        assert_eq!(planner.next_search(), Some((0, REFINE_DEPTH)));
+    }
+
+    #[test]
+    fn lost() {
+        load_network();
+        // no tile can move or merge
+        let board = Board {
+            data: Data(0x1212_2121_1212_2121),
+            ..Board::new(290797)
+        };
+        let mut planner = Planner::new(board, 0);
+        planner.think(&TimeManager::new());
+        assert!(planner.finished());
+        assert_eq!(planner.commit(), None);
+    }
+}
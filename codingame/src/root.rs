@@ -1,35 +1,49 @@
-//python:replace use std::fmt;
-//python:replace use std::mem;
-//python:replace use std::mem::transmute;
-//python:replace use std::io;
-//python:replace use std::arch::x86_64::*;
-//python:replace use std::ops;
-//python:replace {data.rs}
-//python:replace {bin.rs}
-//python:replace {engine.rs}
-//python:replace {input.rs}
-//python:replace {network.rs}
-//python:replace {score.rs}
-//python:replace {timer.rs}
+//! Crate root of the CodinGame submission, flattened into a single file by
+//! `cargo run --bin bundle`.
+
+#![allow(clippy::needless_range_loop, clippy::missing_transmute_annotations)]
+
+use crate::{engine::Board, network::load_network, plan::Planner, timer::TimeManager};
+
+#[macro_use]
+mod data;
+mod bin;
+mod engine;
+mod input;
+mod network;
+mod plan;
+mod score;
+mod timer;
+mod weights;
 
 fn main() {
     let mut timer = TimeManager::new();
+    if weights::ORIGIN.is_none() {
+        eprintln!("playing with the placeholder weights of src/weights.rs");
+    }
     load_network();
-    let mut board = Board::from_input();
-    let mut ans = String::with_capacity(20000);
+    let mut planner = match Board::from_input() {
+        Ok((board, score)) => Planner::new(board, score),
+        Err(e) => return eprintln!("{}", e),
+    };
     loop {
-        let m = board.auto_ai();
-        ans.push(m.0);
-        if let Some(b) = m.1 {
-            board = b;
-            if !timer.ok() {
-                println!("{}", ans);
-                ans.clear();
-                timer.next();
-            }
-        } else {
+        planner.think(&timer);
+        // nothing legal is left to send on a lost board
+        let Some(moves) = planner.commit() else {
+            break;
+        };
+        println!("{}", moves);
+        if planner.finished() {
             break;
         }
+        let turn = match timer.next() {
+            Ok(turn) => turn,
+            Err(e) => return eprintln!("{}", e),
+        };
+        // carry on from the referee's state
+        if let Some(desync) = planner.board.verify(planner.score, &turn) {
+            eprintln!("desync: {}", desync);
+            planner = Planner::new(Board::from_turn(&turn), turn.score);
+        }
     }
-    println!("{}", ans);
 }
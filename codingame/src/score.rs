@@ -1,79 +1,52 @@
-use crate::{engine::Board, network::NETWORK}; //python:del
+use crate::{
+    engine::{Board, Direction},
+    network::network,
+    weights::POINTS_WEIGHT,
+};
 
 impl Board {
+    /// The network value plus `POINTS_WEIGHT` times the merge points, zero
+    /// to search for survival only. The points are counted as
+    /// `Data::potential`, which is off by the same amount for every leaf of a
+    /// search since they all saw the same spawns.
+    ///
+    /// This differs from the trainer on one merge: two 32768s leave one, so
+    /// the potential drops by `14 * 32768` where the trainer's `calc_points`
+    /// credits 65536, and with a nonzero `POINTS_WEIGHT` the search avoids
+    /// the merge the bot was trained to make. Crediting it would mean
+    /// counting capped merges at every node of the search for a board
+    /// holding two 32768s, past what the bot reaches.
     fn score(&self) -> f32 {
-        unsafe { NETWORK.calc(&self.to_t8()) }
+        let value = network().calc(&self.to_t8());
+        value + POINTS_WEIGHT * self.data.potential() as f32
     }
 
-    pub fn auto_ai(&self) -> (char, Option<Self>) {
+    #[allow(dead_code)]
+    pub fn auto_ai(&self) -> Option<(Direction, Self)> {
         self.ai(5)
     }
 
-    pub fn ai(&self, depth: usize) -> (char, Option<Self>) {
+    /// The best move `depth` moves ahead and the board after it, `None` once
+    /// no move is left. Ties go to the first move in `Direction::ALL`.
+    pub fn ai(&self, depth: usize) -> Option<(Direction, Self)> {
         let mut score = -100_000_000f32;
-        let moves = unsafe { self.moves() };
         let mut res = None;
-        let mut c = 'U';
-        if self.data != moves.down {
-            let board = self.spawn(moves.down, moves.free_ud);
+        for (dir, board) in self.successors() {
             let s = board.node(depth - 1);
             if s > score {
-                res = Some(board);
                 score = s;
-                c = 'D';
+                res = Some((dir, board));
             }
         }
-        if self.data != moves.right {
-            let board = self.spawn(moves.right, moves.free_rl);
-            let s = board.node(depth - 1);
-            if s > score {
-                res = Some(board);
-                score = s;
-                c = 'R';
-            }
-        }
-        if self.data != moves.up {
-            let board = self.spawn(moves.up, moves.free_ud);
-            let s = board.node(depth - 1);
-            if s > score {
-                res = Some(board);
-                score = s;
-                c = 'U';
-            }
-        }
-        if self.data != moves.left {
-            let board = self.spawn(moves.left, moves.free_rl);
-            let s = board.node(depth - 1);
-            if s > score {
-                res = Some(board);
-                c = 'L';
-            }
-        }
-        (c, res)
+        res
     }
 
     fn node(&self, depth: usize) -> f32 {
         if depth == 0 {
             return self.score();
         }
-        let mut score = -100_000f32;
-        let moves = unsafe { self.moves() };
-        if self.data != moves.down {
-            let board = self.spawn(moves.down, moves.free_ud);
-            score = score.max(board.node(depth - 1));
-        }
-        if self.data != moves.right {
-            let board = self.spawn(moves.right, moves.free_rl);
-            score = score.max(board.node(depth - 1));
-        }
-        if self.data != moves.up {
-            let board = self.spawn(moves.up, moves.free_ud);
-            score = score.max(board.node(depth - 1));
-        }
-        if self.data != moves.left {
-            let board = self.spawn(moves.left, moves.free_rl);
-            score = score.max(board.node(depth - 1));
-        }
-        score
+        self.successors()
+            .map(|(_, board)| board.node(depth - 1))
+            .fold(-100_000f32, f32::max)
     }
 }
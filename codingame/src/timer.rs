@@ -1,4 +1,7 @@
 #![allow(dead_code)]
+use crate::input::{InputError, Turn};
+use std::time::Duration;
+
 pub struct TimeManager {
     start: std::time::Instant,
     first: bool,
@@ -14,21 +17,20 @@ impl TimeManager {
     }
 
     pub fn ok(&self) -> bool {
-        let ep = self.start.elapsed().as_millis();
-        if self.first {
-            return ep < 990;
-        } else {
-            return ep < 40;
-        }
+        !self.left().is_zero()
     }
 
-    pub fn next(&mut self) {
-        let mut buf = String::new();
-        for _ in 0..6 {
-            std::io::stdin().read_line(&mut buf).unwrap();
-            buf.clear();
-        }
+    /// Time left in this turn.
+    pub fn left(&self) -> Duration {
+        let limit = if self.first { 990 } else { 40 };
+        Duration::from_millis(limit).saturating_sub(self.start.elapsed())
+    }
+
+    /// Waits for the input of the next turn and restarts the clock.
+    pub fn next(&mut self) -> Result<Turn, InputError> {
+        let turn = Turn::read(&mut std::io::stdin().lock());
         self.start = std::time::Instant::now();
         self.first = false;
+        turn
     }
 }
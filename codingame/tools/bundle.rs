@@ -0,0 +1,386 @@
+//! Bundles the CodinGame submission into a single `main.rs`.
+//!
+//! Starting from the entry file, every `mod name;` declaration is replaced
+//! by `mod name { .. }` holding the module file, recursively, so the bundle
+//! keeps the module tree and compiles like the crate. Items under
+//! `#[cfg(test)]` are dropped. `--minify` also strips comments and
+//! whitespace with a small Rust lexer, so string literals containing `//`
+//! survive.
+//!
+//! The placeholder weights in `src/weights.rs` are refused unless
+//! `--placeholder` is given, so an untrained bot is never submitted by
+//! accident.
+//!
+//! usage: bundle [--entry src/root.rs] [--output dist/main.rs] [--minify]
+//!               [--limit 100000] [--check] [--placeholder]
+
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command};
+
+/// Characters CodinGame accepts in a submission.
+const LIMIT: usize = 100_000;
+
+/// The weights the submission is built with.
+#[allow(dead_code)]
+mod weights {
+    include!("../src/weights.rs");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Word,
+    Literal,
+    Punct,
+    Comment,
+    Space,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    kind: Kind,
+    text: &'a str,
+    start: usize,
+}
+
+/// Length of the string, raw string, char or byte literal at the start of
+/// `s`, if there is one.
+fn literal(s: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    if s.first() == Some(&b'b') {
+        i += 1;
+    }
+    if s.get(i) == Some(&b'r') && matches!(s.get(i + 1), Some(b'"') | Some(b'#')) {
+        let hashes = s[i + 1..].iter().take_while(|c| **c == b'#').count();
+        let open = i + 1 + hashes;
+        if s.get(open) != Some(&b'"') {
+            return None;
+        }
+        let close = format!("\"{}", "#".repeat(hashes));
+        let end = std::str::from_utf8(&s[open + 1..]).ok()?.find(&close)?;
+        return Some(open + 1 + end + close.len());
+    }
+    match s.get(i) {
+        Some(b'"') => {
+            let mut j = i + 1;
+            while j < s.len() {
+                match s[j] {
+                    b'\\' => j += 2,
+                    b'"' => return Some(j + 1),
+                    _ => j += 1,
+                }
+            }
+            None
+        }
+        Some(b'\'') => {
+            // a char literal, unless it is a lifetime such as 'a
+            let mut j = i + 1;
+            if s.get(j) == Some(&b'\\') {
+                j += 2;
+                while j < s.len() && s[j] != b'\'' {
+                    j += 1;
+                }
+                return Some(j + 1);
+            }
+            let width = match s.get(j)? {
+                c if *c < 0x80 => 1,
+                c if *c >= 0xf0 => 4,
+                c if *c >= 0xe0 => 3,
+                _ => 2,
+            };
+            (s.get(j + width) == Some(&b'\'')).then_some(j + width + 1)
+        }
+        _ => None,
+    }
+}
+
+fn lex(source: &str) -> Vec<Token<'_>> {
+    let s = source.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        let (kind, len) = if rest.starts_with(b"//") {
+            let len = rest.iter().position(|c| *c == b'\n').unwrap_or(rest.len());
+            (Kind::Comment, len)
+        } else if rest.starts_with(b"/*") {
+            let (mut depth, mut j) = (0, 0);
+            loop {
+                if rest[j..].starts_with(b"/*") {
+                    depth += 1;
+                    j += 2;
+                } else if rest[j..].starts_with(b"*/") {
+                    depth -= 1;
+                    j += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else if j >= rest.len() {
+                    break;
+                } else {
+                    j += 1;
+                }
+            }
+            (Kind::Comment, j.min(rest.len()))
+        } else if rest[0].is_ascii_whitespace() {
+            let len = rest.iter().take_while(|c| c.is_ascii_whitespace()).count();
+            (Kind::Space, len)
+        } else if let Some(len) = literal(rest) {
+            (Kind::Literal, len)
+        } else if rest[0] == b'\'' {
+            // lifetime or label
+            let len = 1 + rest[1..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+                .count();
+            (Kind::Word, len)
+        } else if rest[0].is_ascii_alphanumeric() || rest[0] == b'_' || rest[0] >= 0x80 {
+            let mut len = 0;
+            while len < rest.len() {
+                let c = rest[len];
+                let fraction = c == b'.'
+                    && rest[0].is_ascii_digit()
+                    && rest.get(len + 1).is_some_and(u8::is_ascii_digit);
+                if !(c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80 || fraction) {
+                    break;
+                }
+                len += 1;
+            }
+            (Kind::Word, len)
+        } else {
+            (Kind::Punct, 1)
+        };
+        tokens.push(Token {
+            kind,
+            text: &source[i..i + len],
+            start: i,
+        });
+        i += len;
+    }
+    tokens
+}
+
+/// Indices of the tokens that are not whitespace or comments.
+fn code(tokens: &[Token]) -> Vec<usize> {
+    (0..tokens.len())
+        .filter(|i| !matches!(tokens[*i].kind, Kind::Space | Kind::Comment))
+        .collect()
+}
+
+fn texts<'a>(tokens: &[Token<'a>], code: &[usize], at: usize, n: usize) -> Vec<&'a str> {
+    code[at..(at + n).min(code.len())]
+        .iter()
+        .map(|i| tokens[*i].text)
+        .collect()
+}
+
+/// End (exclusive, in `code`) of the item starting at `code[at]`.
+fn item_end(tokens: &[Token], code: &[usize], at: usize) -> usize {
+    let mut depth = 0;
+    for (j, i) in code.iter().enumerate().skip(at) {
+        match tokens[*i].text {
+            "{" | "(" | "[" => depth += 1,
+            "}" | ")" | "]" => {
+                depth -= 1;
+                if depth == 0 && tokens[*i].text == "}" {
+                    return j + 1;
+                }
+            }
+            ";" if depth == 0 => return j + 1,
+            _ => {}
+        }
+    }
+    code.len()
+}
+
+/// Inlines the modules declared in `path`. `dir` is where its child modules
+/// live.
+fn expand(path: &Path, dir: &Path) -> Result<String, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let tokens = lex(&source);
+    let code = code(&tokens);
+    let mut out = String::new();
+    let mut copied = 0;
+    let mut at = 0;
+    while at < code.len() {
+        let start = tokens[code[at]].start;
+        if texts(&tokens, &code, at, 7) == ["#", "[", "cfg", "(", "test", ")", "]"] {
+            let end = item_end(&tokens, &code, at + 7);
+            out.push_str(&source[copied..start]);
+            copied = tokens[code[end - 1]].start + tokens[code[end - 1]].text.len();
+            at = end;
+            continue;
+        }
+        let head = texts(&tokens, &code, at, 3);
+        if head.len() == 3 && head[0] == "mod" && head[2] == ";" {
+            let name = head[1];
+            let file = dir.join(format!("{}.rs", name));
+            let (file, children) = if file.exists() {
+                (file, dir.join(name))
+            } else {
+                (dir.join(name).join("mod.rs"), dir.join(name))
+            };
+            let inner = expand(&file, &children)?;
+            out.push_str(&source[copied..start]);
+            out.push_str(&format!("mod {} {{\n{}}}", name, inner));
+            copied = tokens[code[at + 2]].start + 1;
+            at += 3;
+            continue;
+        }
+        at += 1;
+    }
+    out.push_str(&source[copied..]);
+    Ok(out)
+}
+
+/// Drops comments and every space that does not separate two words or two
+/// operator characters.
+fn minify(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut last: Option<Token> = None;
+    let mut gap = false;
+    for token in lex(source) {
+        match token.kind {
+            Kind::Space | Kind::Comment => gap = true,
+            kind => {
+                if let Some(last) = last {
+                    let word = |k: Kind| k != Kind::Punct;
+                    let separate = if word(last.kind) && word(kind) {
+                        true
+                    } else if word(last.kind) || word(kind) {
+                        false
+                    } else {
+                        // `: :` is not `::`, `> =` is not `>=`
+                        let joins = |t: &str| "=<>!&|+-*/%^.:".contains(t);
+                        gap && joins(last.text) && joins(token.text)
+                    };
+                    if separate {
+                        out.push(' ');
+                    }
+                }
+                out.push_str(token.text);
+                last = Some(token);
+                gap = false;
+            }
+        }
+    }
+    out
+}
+
+pub fn bundle(entry: &Path, minified: bool) -> Result<String, String> {
+    let dir = entry.parent().unwrap_or(Path::new("."));
+    let bundle = expand(entry, dir)?;
+    Ok(if minified { minify(&bundle) } else { bundle })
+}
+
+/// Compiles the bundle with `rustc` and returns its diagnostics on failure.
+fn check(path: &Path) -> Result<(), String> {
+    let out = std::env::temp_dir().join(format!("2048-bundle-{}", std::process::id()));
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(rustc)
+        .args(["--edition", "2021", "-O", "--crate-type", "bin", "-o"])
+        .arg(&out)
+        .arg(path)
+        .output()
+        .map_err(|e| format!("failed to run rustc: {}", e))?;
+    let _ = std::fs::remove_file(&out);
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut entry = PathBuf::from("src/root.rs");
+    let mut output = PathBuf::from("dist/main.rs");
+    let mut limit = LIMIT;
+    let (mut minified, mut compile, mut placeholder) = (false, false, false);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--entry" => entry = PathBuf::from(value()?),
+            "--output" => output = PathBuf::from(value()?),
+            "--limit" => {
+                let v = value()?;
+                limit = v.parse().map_err(|_| format!("invalid limit {}", v))?;
+            }
+            "--minify" => minified = true,
+            "--check" => compile = true,
+            "--placeholder" => placeholder = true,
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    if weights::ORIGIN.is_none() && !placeholder {
+        return Err(
+            "src/weights.rs holds placeholder weights, write trained ones with `ai pack` \
+             or pass --placeholder"
+                .to_string(),
+        );
+    }
+    let bundle = bundle(&entry, minified)?;
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(&output, &bundle)
+        .map_err(|e| format!("failed to write {}: {}", output.display(), e))?;
+    let chars = bundle.chars().count();
+    println!(
+        "bundled {} into {}: {} chars, {} bytes",
+        entry.display(),
+        output.display(),
+        chars,
+        bundle.len()
+    );
+    if compile {
+        check(&output)?;
+        println!("{} compiles", output.display());
+    }
+    if chars > limit {
+        return Err(format!("{} chars exceed the limit of {}", chars, limit));
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("bundle: {}", e);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn minified() {
+        let source = "fn main() {\n    // a comment\n    let url = \"https://a.b\"; /* x /* y */ */\n    \
+                      let c = '/'; let l: &'static str = r#\"//\"#;\n    let x: ::std::primitive::u8 = 1;\n    \
+                      if x > =1 {}\n}\n";
+        assert_eq!(
+            minify(source),
+            "fn main(){let url=\"https://a.b\";let c='/';let l: &'static str=r#\"//\"#;\
+             let x: ::std::primitive::u8=1;if x> =1{}}"
+        );
+        assert_eq!(minify("let a = 1.5e3 as f32;"), "let a=1.5e3 as f32;");
+    }
+
+    #[test]
+    fn submission() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let bundle = bundle(&dir.join("src/root.rs"), false).unwrap();
+        assert!(bundle.contains("mod engine {"));
+        assert!(!bundle.contains("mod engine;"));
+        assert!(!bundle.contains("cfg(test)"));
+        let minified = minify(&bundle);
+        assert!(minified.chars().count() < LIMIT);
+
+        let path = std::env::temp_dir().join(format!("2048-bundle-test-{}.rs", std::process::id()));
+        std::fs::write(&path, minified).unwrap();
+        let checked = check(&path);
+        std::fs::remove_file(&path).unwrap();
+        checked.unwrap();
+    }
+}
@@ -0,0 +1,562 @@
+//! Local stand-in for the CodinGame 2048 referee.
+//!
+//! Plays one game per seed against a bot started as a subprocess. Every turn
+//! the bot gets the seed of the next spawn, the score and the 4 rows of the
+//! grid (the 6 lines `Board::from_input` and `TimeManager::next` read) and
+//! answers with one line of `UDLR` moves, applied in order. The answer must
+//! come within 1000 ms on the first turn and 50 ms on the others. Tiles
+//! spawn as in the Java referee: at free cell `seed % free` in column-major
+//! order, a 4 if bit 4 of the seed is set, then `seed = seed^2 % 50515093`.
+//!
+//! `--golden` writes the golden traces the engines are tested against
+//! instead: games played with these rules from fixed starts, with the board
+//! after every move.
+//!
+//! usage: referee [--seeds 1..11] [--verbose] -- <bot> [args..]
+//!        referee --golden ../golden/traces.txt
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{exit, Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+const SEED_MOD: u64 = 50515093;
+const FIRST_TURN: Duration = Duration::from_millis(1000);
+const TURN: Duration = Duration::from_millis(50);
+
+/// Games of the golden traces: what they cover, the start (`None` for a new
+/// game), the seed and the moves (`None` to play the first move of `DLRU`
+/// that changes something until the game is over).
+const GOLDEN: [(&str, Option<&str>, u64, Option<&str>); 10] = [
+    ("new game", None, 42, None),
+    ("new game", None, 2, None),
+    ("new game", None, 290797, None),
+    ("new game", None, 50515092, None),
+    (
+        "row of four 2s",
+        Some("1111/0000/0000/0000"),
+        3,
+        Some("LRL"),
+    ),
+    (
+        "column of four 2s",
+        Some("1000/1000/1000/1000"),
+        3,
+        Some("UDU"),
+    ),
+    (
+        "tiles merge once per move",
+        Some("1120/2220/0000/0000"),
+        5,
+        Some("LR"),
+    ),
+    (
+        "16384s merge into 32768",
+        Some("ee00/0000/0000/0000"),
+        7,
+        Some("LD"),
+    ),
+    (
+        "32768s merge into 65536, beyond the engines",
+        Some("ff00/0000/0000/0000"),
+        13,
+        Some("L"),
+    ),
+    ("last free cell", Some("1212/2121/1212/2120"), 11, None),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Game {
+    /// Tile values, `grid[x][y]` with `y` the row.
+    grid: [[u32; 4]; 4],
+    seed: u64,
+    score: u64,
+    moves: usize,
+}
+
+impl Game {
+    fn new(seed: u64) -> Self {
+        let mut game = Game {
+            grid: [[0; 4]; 4],
+            seed: seed % SEED_MOD,
+            score: 0,
+            moves: 0,
+        };
+        game.spawn();
+        game.spawn();
+        game
+    }
+
+    fn spawn(&mut self) {
+        let mut free = vec![];
+        for x in 0..4 {
+            for y in 0..4 {
+                if self.grid[x][y] == 0 {
+                    free.push((x, y));
+                }
+            }
+        }
+        let (x, y) = free[(self.seed % free.len() as u64) as usize];
+        self.grid[x][y] = if self.seed & 0x10 == 0 { 2 } else { 4 };
+        self.seed = self.seed * self.seed % SEED_MOD;
+    }
+
+    /// Cells of the 4 lines of a move, each starting at the edge the tiles
+    /// move to.
+    fn lines(dir: char) -> Option<[[(usize, usize); 4]; 4]> {
+        let cell = |line: usize, i: usize| match dir {
+            'U' => (line, i),
+            'D' => (line, 3 - i),
+            'L' => (i, line),
+            'R' => (3 - i, line),
+            _ => unreachable!(),
+        };
+        "UDLR"
+            .contains(dir)
+            .then(|| [0, 1, 2, 3].map(|line| [0, 1, 2, 3].map(|i| cell(line, i))))
+    }
+
+    /// Slides and merges the tiles, every tile merging at most once, and
+    /// spawns a tile. Returns false, leaving the game as it is, if the move
+    /// changes nothing.
+    fn apply(&mut self, dir: char) -> Result<bool, String> {
+        let lines = Game::lines(dir).ok_or_else(|| format!("invalid move {:?}", dir))?;
+        let mut next = self.clone();
+        for line in lines {
+            let tiles: Vec<u32> = line
+                .iter()
+                .map(|(x, y)| self.grid[*x][*y])
+                .filter(|v| *v != 0)
+                .collect();
+            let mut merged = vec![];
+            let mut i = 0;
+            while i < tiles.len() {
+                if i + 1 < tiles.len() && tiles[i] == tiles[i + 1] {
+                    merged.push(tiles[i] * 2);
+                    next.score += tiles[i] as u64 * 2;
+                    i += 2;
+                } else {
+                    merged.push(tiles[i]);
+                    i += 1;
+                }
+            }
+            for (k, (x, y)) in line.iter().enumerate() {
+                next.grid[*x][*y] = *merged.get(k).unwrap_or(&0);
+            }
+        }
+        if next.grid == self.grid {
+            return Ok(false);
+        }
+        next.moves += 1;
+        next.spawn();
+        *self = next;
+        Ok(true)
+    }
+
+    /// A game from a board in the notation of the engines: tile exponents in
+    /// hex, row by row from the top left, rows separated by `/`.
+    fn from_notation(board: &str, seed: u64) -> Self {
+        let mut game = Game {
+            grid: [[0; 4]; 4],
+            seed,
+            score: 0,
+            moves: 0,
+        };
+        for (y, row) in board.split('/').enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let exponent = c.to_digit(17).unwrap();
+                game.grid[x][y] = if exponent == 0 { 0 } else { 1 << exponent };
+            }
+        }
+        game
+    }
+
+    /// The board as the engines write it, and `g` for 65536, which they
+    /// cannot hold.
+    fn notation(&self) -> String {
+        let exponent = |v: u32| match v {
+            0 => 0,
+            v if v <= 65536 => v.trailing_zeros(),
+            v => panic!("tile {} is beyond the notation", v),
+        };
+        let rows: Vec<String> = (0..4)
+            .map(|y| {
+                (0..4)
+                    .map(|x| char::from_digit(exponent(self.grid[x][y]), 17).unwrap())
+                    .collect()
+            })
+            .collect();
+        rows.join("/")
+    }
+
+    fn over(&self) -> bool {
+        "UDLR".chars().all(|dir| !self.clone().apply(dir).unwrap())
+    }
+
+    fn max_tile(&self) -> u32 {
+        self.grid.iter().flatten().copied().max().unwrap_or(0)
+    }
+
+    /// The 6 lines the bot reads each turn.
+    fn input(&self) -> String {
+        let mut s = format!("{}\n{}\n", self.seed, self.score);
+        for y in 0..4 {
+            let row: Vec<String> = (0..4).map(|x| self.grid[x][y].to_string()).collect();
+            s += &row.join(" ");
+            s.push('\n');
+        }
+        s
+    }
+}
+
+struct Outcome {
+    game: Game,
+    turns: usize,
+    slowest: Duration,
+    error: Option<String>,
+}
+
+struct Bot {
+    child: Child,
+    lines: Receiver<String>,
+}
+
+impl Bot {
+    fn start(command: &[String]) -> Result<Self, String> {
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to start {}: {}", command[0], e))?;
+        let stdout = child.stdout.take().unwrap();
+        let (sender, lines) = channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Bot { child, lines })
+    }
+
+    /// Sends the turn input and waits for the answer.
+    fn turn(&mut self, input: &str, limit: Duration) -> Result<(String, Duration), String> {
+        let stdin = self.child.stdin.as_mut().unwrap();
+        stdin
+            .write_all(input.as_bytes())
+            .and_then(|_| stdin.flush())
+            .map_err(|_| "the bot stopped reading its input".to_string())?;
+        let start = Instant::now();
+        match self.lines.recv_timeout(limit) {
+            Ok(line) => Ok((line, start.elapsed())),
+            Err(RecvTimeoutError::Timeout) => {
+                Err(format!("no answer within {} ms", limit.as_millis()))
+            }
+            Err(RecvTimeoutError::Disconnected) => Err("the bot exited".to_string()),
+        }
+    }
+}
+
+impl Drop for Bot {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn play(command: &[String], seed: u64, verbose: bool) -> Outcome {
+    let mut outcome = Outcome {
+        game: Game::new(seed),
+        turns: 0,
+        slowest: Duration::ZERO,
+        error: None,
+    };
+    let mut bot = match Bot::start(command) {
+        Ok(bot) => bot,
+        Err(e) => {
+            outcome.error = Some(e);
+            return outcome;
+        }
+    };
+    while !outcome.game.over() {
+        let limit = if outcome.turns == 0 { FIRST_TURN } else { TURN };
+        let (answer, elapsed) = match bot.turn(&outcome.game.input(), limit) {
+            Ok(answer) => answer,
+            Err(e) => {
+                outcome.error = Some(format!("turn {}: {}", outcome.turns + 1, e));
+                return outcome;
+            }
+        };
+        outcome.turns += 1;
+        outcome.slowest = outcome.slowest.max(elapsed);
+        if verbose {
+            eprintln!(
+                "turn {} ({} ms): {}",
+                outcome.turns,
+                elapsed.as_millis(),
+                answer
+            );
+        }
+        // no move would resend the same turn forever
+        if answer.trim().is_empty() {
+            outcome.error = Some(format!("turn {}: empty answer", outcome.turns));
+            return outcome;
+        }
+        for dir in answer.trim().chars() {
+            if outcome.game.over() {
+                break;
+            }
+            match outcome.game.apply(dir) {
+                Ok(true) => {}
+                Ok(false) => {
+                    outcome.error = Some(format!(
+                        "turn {}: move {} after {} moves changes nothing",
+                        outcome.turns, dir, outcome.game.moves
+                    ));
+                    return outcome;
+                }
+                Err(e) => {
+                    outcome.error = Some(format!("turn {}: {}", outcome.turns, e));
+                    return outcome;
+                }
+            }
+        }
+    }
+    outcome
+}
+
+/// The golden traces, one game per block: the start, then
+/// `<move> <seed> <score> <board>` after every move and its spawn, then
+/// `over` if no move is left.
+fn golden() -> String {
+    let mut out = String::from(
+        "# Golden traces of the Board.java rules, written by\n\
+         # `cargo run --bin referee -- --golden ../golden/traces.txt` in codingame.\n\
+         # They are played by the local referee's port of those rules, not taken\n\
+         # from the Java referee.\n\
+         #\n\
+         # new <seed> <seed> <board>: a new game, with the seed of the next spawn\n\
+         # game <seed> <board>: a game from a position\n\
+         # <move> <seed> <score> <board>: the game after a move and its spawn\n\
+         # over: no move is left\n\
+         #\n\
+         # Boards hold the tile exponents in hex, row by row from the top left, and\n\
+         # g for 65536: the engines keep one 32768 where two merge.\n",
+    );
+    for (name, start, seed, moves) in GOLDEN {
+        let mut game = match start {
+            Some(board) => Game::from_notation(board, seed),
+            None => Game::new(seed),
+        };
+        out += &format!("\n# {}\n", name);
+        out += &match start {
+            Some(_) => format!("game {} {}\n", game.seed, game.notation()),
+            None => format!("new {} {} {}\n", seed, game.seed, game.notation()),
+        };
+        let mut moves = moves.map(|m| m.chars());
+        loop {
+            let dir = match moves.as_mut() {
+                Some(moves) => match moves.next() {
+                    Some(dir) => dir,
+                    None => break,
+                },
+                None => match "DLRU".chars().find(|d| game.clone().apply(*d).unwrap()) {
+                    Some(dir) => dir,
+                    None => break,
+                },
+            };
+            assert!(game.apply(dir).unwrap(), "{} {} changes nothing", name, dir);
+            out += &format!("{} {} {} {}\n", dir, game.seed, game.score, game.notation());
+        }
+        if game.over() {
+            out += "over\n";
+        }
+    }
+    out
+}
+
+fn parse_seeds(s: &str) -> Result<Vec<u64>, String> {
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u64>()
+            .map_err(|_| format!("invalid seed {:?}", v))
+    };
+    let mut seeds = Vec::new();
+    for part in s.split(',').filter(|p| !p.trim().is_empty()) {
+        if let Some((start, end)) = part.split_once("..=") {
+            seeds.extend(parse(start)?..=parse(end)?);
+        } else if let Some((start, end)) = part.split_once("..") {
+            seeds.extend(parse(start)?..parse(end)?);
+        } else {
+            seeds.push(parse(part)?);
+        }
+    }
+    if seeds.is_empty() {
+        return Err("no seeds given".to_string());
+    }
+    Ok(seeds)
+}
+
+fn run() -> Result<bool, String> {
+    let mut seeds = parse_seeds("1..11")?;
+    let mut verbose = false;
+    let mut args = std::env::args().skip(1);
+    let mut command = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seeds" => seeds = parse_seeds(&args.next().ok_or("--seeds needs a value")?)?,
+            "--verbose" => verbose = true,
+            "--golden" => {
+                let path = args.next().ok_or("--golden needs a file")?;
+                std::fs::write(&path, golden())
+                    .map_err(|e| format!("failed to write {}: {}", path, e))?;
+                println!("wrote the golden traces to {}", path);
+                return Ok(true);
+            }
+            "--" => {
+                command = args.by_ref().collect();
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    if command.is_empty() {
+        return Err(
+            "usage: referee [--seeds 1..11] [--verbose] -- <bot> [args..]\n       \
+             referee --golden <file>"
+                .to_string(),
+        );
+    }
+    let mut scores = vec![];
+    let mut failed = 0;
+    for seed in seeds {
+        let outcome = play(&command, seed, verbose);
+        let game = &outcome.game;
+        print!(
+            "seed {}: score {}, max tile {}, {} moves in {} turns, slowest turn {} ms",
+            seed,
+            game.score,
+            game.max_tile(),
+            game.moves,
+            outcome.turns,
+            outcome.slowest.as_millis()
+        );
+        match outcome.error {
+            Some(e) => {
+                println!(", lost: {}", e);
+                failed += 1;
+            }
+            None => println!(),
+        }
+        scores.push(game.score);
+    }
+    println!(
+        "{} games, {} lost, score mean {:.1} min {} max {}",
+        scores.len(),
+        failed,
+        scores.iter().sum::<u64>() as f64 / scores.len() as f64,
+        scores.iter().min().unwrap(),
+        scores.iter().max().unwrap()
+    );
+    Ok(failed == 0)
+}
+
+fn main() {
+    match run() {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(e) => {
+            eprintln!("referee: {}", e);
+            exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn game(rows: [[u32; 4]; 4]) -> Game {
+        let mut game = Game::new(1);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, v) in row.iter().enumerate() {
+                game.grid[x][y] = *v;
+            }
+        }
+        game
+    }
+
+    #[test]
+    fn rules() {
+        // the first spawns go to free cells in column-major order
+        let start = Game::new(7);
+        assert_eq!(start.grid[1][3], 2);
+        assert_eq!(start.grid[1][0], 4);
+        assert_eq!(start.seed, 7 * 7 * 7 * 7 % SEED_MOD);
+        assert_eq!(start.input().lines().count(), 6);
+        assert_eq!(start.input().lines().nth(2), Some("0 4 0 0"));
+        // squaring a seed of 2^32 or more would overflow
+        assert_eq!(Game::new(u64::MAX), Game::new(u64::MAX % SEED_MOD));
+
+        let mut row = game([[2, 2, 2, 2], [0; 4], [0; 4], [0; 4]]);
+        assert!(row.apply('L').unwrap());
+        assert_eq!(&row.grid.map(|c| c[0])[..], [4, 4, 0, 0]);
+        assert_eq!(row.score, 8);
+        assert_eq!(row.moves, 1);
+        assert_eq!(row.grid.iter().flatten().filter(|v| **v != 0).count(), 3);
+
+        let mut stuck = game([[2, 4, 0, 0], [0; 4], [0; 4], [0; 4]]);
+        assert!(!stuck.apply('L').unwrap());
+        assert!(stuck.apply('X').is_err());
+        let full = game([[2, 4, 2, 4], [4, 2, 4, 2], [2, 4, 2, 4], [4, 2, 4, 2]]);
+        assert!(full.over());
+    }
+
+    #[test]
+    fn golden() {
+        let game = Game::from_notation("1000/0200/00f0/0000", 5);
+        assert_eq!(
+            (game.grid[0][0], game.grid[1][1], game.grid[2][2]),
+            (2, 4, 32768)
+        );
+        assert_eq!(game.notation(), "1000/0200/00f0/0000");
+        assert_eq!(
+            Game::from_notation("g000/0000/0000/0000", 5).grid[0][0],
+            65536
+        );
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../golden/traces.txt");
+        let traces = std::fs::read_to_string(path).unwrap();
+        assert!(
+            traces == super::golden(),
+            "{} is out of date, run the referee with --golden",
+            path
+        );
+    }
+
+    fn sh(script: &str) -> Vec<String> {
+        vec!["sh".to_string(), "-c".to_string(), script.to_string()]
+    }
+
+    #[test]
+    fn bots() {
+        // reads each turn and cycles through the moves until none works
+        let cycler = sh(
+            "while read seed; do read score; read a; read b; read c; read d; \
+                         echo LDRULDRULDRULDRULDRULDRU; done",
+        );
+        let outcome = play(&cycler, 3, false);
+        assert!(outcome.turns >= 1 && outcome.game.moves >= 1);
+        assert!(outcome.error.is_some() || outcome.game.over());
+
+        let slow = play(&sh("sleep 2"), 3, false);
+        assert_eq!(slow.error.unwrap(), "turn 1: no answer within 1000 ms");
+        let quitter = play(&sh("read seed"), 3, false);
+        assert_eq!(quitter.error.unwrap(), "turn 1: the bot exited");
+        let wrong = play(&sh("echo X; sleep 1"), 3, false);
+        assert_eq!(wrong.error.unwrap(), "turn 1: invalid move 'X'");
+        let blank = play(&sh("echo; echo ' '; sleep 1"), 3, false);
+        assert_eq!(blank.error.unwrap(), "turn 1: empty answer");
+    }
+}
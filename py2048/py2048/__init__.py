@@ -1,4 +1,5 @@
 from .py2048 import *
 from .base import *
+from .model import *
 
 __version__ = "0.1.0"
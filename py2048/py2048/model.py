@@ -0,0 +1,58 @@
+from dataclasses import dataclass
+from typing import Dict, List
+
+
+@dataclass
+class Model:
+    """A network exported by the `ai` trainer as `.npz`.
+
+    `layers` maps the layer names of the `network!` macro (`a`, `b`, ...,
+    `output_layer`) to their weights and biases.
+    """
+    layers: Dict[str, Dict[str, 'numpy.ndarray']]
+    order: List[str]
+    fitness: float | None = None
+
+    def value(self, board) -> float:
+        """Evaluates the network on a `Board`, like `Network::calc`."""
+        import numpy as np
+        x = np.array(list(board), dtype=np.float32) / 15.0
+        for name in self.order:
+            layer = self.layers[name]
+            x = np.maximum(layer['weights'] @ x + layer['biases'], 0.0)
+        return float(self.layers['output_layer']['weights'] @ x)
+
+
+def _split(arrays, index=None) -> Model:
+    layers: Dict[str, Dict] = {}
+    order = []
+    for key in arrays.files:
+        if '.' not in key:
+            continue
+        name, kind = key.split('.', 1)
+        value = arrays[key] if index is None else arrays[key][index]
+        layers.setdefault(name, {})[kind] = value
+        if name != 'output_layer' and name not in order:
+            order.append(name)
+    fitness = None
+    if index is not None and 'fitness' in arrays.files:
+        fitness = float(arrays['fitness'][index])
+    return Model(layers, order, fitness)
+
+
+def load_model(path) -> Model:
+    """Loads a single network exported by `ai::npz`."""
+    import numpy as np
+    arrays = np.load(path)
+    if 'fitness' in arrays.files:
+        return _split(arrays, 0)
+    return _split(arrays)
+
+
+def load_population(path) -> List[Model]:
+    """Loads every bot of a population exported by `ai::npz`."""
+    import numpy as np
+    arrays = np.load(path)
+    if 'fitness' not in arrays.files:
+        return [_split(arrays)]
+    return [_split(arrays, i) for i in range(len(arrays['fitness']))]
@@ -136,4 +136,70 @@ mod tests {
         let moves = unsafe { board.moves() };
         println!("{}", moves.down);
     }
+
+    /// A trace board as the engine holds it: the 65536 (`g`) of two merged
+    /// 32768s stays a 32768.
+    fn parse(board: &str) -> u64 {
+        let tiles = board.chars().filter(|c| *c != '/');
+        let tiles = tiles.map(|c| c.to_digit(17).unwrap().min(15) as u64);
+        tiles.enumerate().map(|(i, t)| t << (i * 4)).sum()
+    }
+
+    fn apply(board: &Board, dir: char) -> Option<Board> {
+        let moves = unsafe { board.moves() };
+        let next = match dir {
+            'U' => moves.up,
+            'D' => moves.down,
+            'L' => moves.left,
+            'R' => moves.right,
+            _ => panic!("invalid move {}", dir),
+        };
+        (next.data != board.data).then_some(next)
+    }
+
+    #[test]
+    fn golden() {
+        let traces = include_str!("../../golden/traces.txt");
+        let mut game = None;
+        for (i, line) in traces.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [] => {}
+                ["#", ..] => {}
+                ["new", seed, next, board] => {
+                    let board_new = Board::new(seed.parse().unwrap());
+                    assert_eq!(board_new.data, parse(board), "line {}", i + 1);
+                    assert_eq!(
+                        board_new.seed,
+                        next.parse::<u64>().unwrap(),
+                        "line {}",
+                        i + 1
+                    );
+                    game = Some(board_new);
+                }
+                ["game", seed, board] => {
+                    game = Some(Board {
+                        seed: seed.parse().unwrap(),
+                        data: parse(board),
+                    });
+                }
+                ["over"] => {
+                    let board = game.as_ref().unwrap();
+                    for dir in "UDLR".chars() {
+                        assert!(apply(board, dir).is_none(), "line {}: {} moves", i + 1, dir);
+                    }
+                }
+                // py2048 keeps no score
+                [dir, seed, _, board] => {
+                    let board_before = game.as_mut().unwrap();
+                    let next = apply(board_before, dir.chars().next().unwrap()).unwrap();
+                    assert_eq!(next.data, parse(board), "line {}", i + 1);
+                    assert_eq!(next.seed, seed.parse::<u64>().unwrap(), "line {}", i + 1);
+                    *board_before = next;
+                }
+                _ => panic!("line {}: {:?}", i + 1, line),
+            }
+        }
+        assert!(game.is_some());
+    }
 }
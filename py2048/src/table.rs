@@ -1,120 +1,92 @@
-use once_cell::sync::Lazy;
-
-fn u16_to_data(d: u16) -> [i32; 4] {
-    let mut data = [0; 4];
-    for i in 0..4 {
-        data[i] = ((d >> (i * 4)) & 0xf) as i32;
-    }
-    data
-}
-
-fn data_to_u16(data: &[i32; 4]) -> u16 {
-    let mut d = 0;
-    for i in 0..4 {
-        d |= (data[i] as u16) << (i * 4);
-    }
-    d
-}
-
-fn data_to_u64(data: &[i32; 4]) -> u64 {
-    let mut d = 0;
-    for i in 0..4 {
-        d |= (data[i] as u64) << (i * 16);
-    }
-    d
-}
-
-fn calc_line_l(data: &[i32; 4]) -> [i32; 4] {
-    let mut res = [0; 4];
-    let mut index = 0;
-    let mut flag = false;
-    for num in data.iter() {
-        if *num != 0 {
-            if flag && res[index - 1] == *num {
-                if res[index - 1] != 15 {
-                    res[index - 1] += 1;
+/// A line after a move towards its low bits, the first tile being the
+/// lowest nibble. Tiles stop at 15.
+const fn move_line(line: u16) -> u16 {
+    let mut res = 0;
+    let mut shift = 0;
+    let mut last = 0;
+    let mut i = 0;
+    while i < 16 {
+        let tile = (line >> i) & 0xf;
+        if tile != 0 {
+            if tile == last {
+                if tile != 15 {
+                    res += 1 << (shift - 4);
                 }
-                flag = false;
+                last = 0;
             } else {
-                res[index] = *num;
-                index += 1;
-                flag = true;
+                res |= tile << shift;
+                shift += 4;
+                last = tile;
             }
         }
+        i += 4;
     }
     res
 }
 
-fn calc_line_r(data: &[i32; 4]) -> [i32; 4] {
-    let mut res = [0; 4];
-    let mut index = 4;
-    let mut flag = false;
-    for num in data.iter().rev() {
-        if *num != 0 {
-            if flag && res[index] == *num {
-                if res[index] != 15 {
-                    res[index] += 1;
-                }
-                flag = false;
-            } else {
-                index -= 1;
-                res[index] = *num;
-                flag = true;
-            }
-        }
-    }
-    res
+const fn reverse(line: u16) -> u16 {
+    (line >> 12) | ((line >> 4) & 0xf0) | ((line << 4) & 0xf00) | (line << 12)
 }
 
+/// A row as a column: tile `i` goes to bits `16 * i`.
+const fn to_col(line: u16) -> u64 {
+    let line = line as u64;
+    (line & 0xf) | ((line & 0xf0) << 12) | ((line & 0xf00) << 24) | ((line & 0xf000) << 36)
+}
+
+const fn free(line: u16) -> usize {
+    let tiles = line | (line >> 1) | (line >> 2) | (line >> 3);
+    4 - (tiles & 0x1111).count_ones() as usize
+}
+
+#[derive(Clone, Copy)]
 pub struct RowData {
     pub right: u16,
     pub left: u16,
     pub free: usize,
 }
 
-impl RowData {
-    fn new(data_r: &[i32; 4], data_l: &[i32; 4]) -> Self {
-        RowData {
-            right: data_to_u16(data_r),
-            left: data_to_u16(data_l),
-            free: data_r.iter().filter(|&x| *x == 0).count(),
-        }
-    }
-}
-
+#[derive(Clone, Copy)]
 pub struct ColData {
     pub up: u64,
     pub down: u64,
     pub free: usize,
 }
 
-impl ColData {
-    fn new(data_u: &[i32; 4], data_d: &[i32; 4]) -> Self {
-        ColData {
-            up: data_to_u64(data_u),
-            down: data_to_u64(data_d),
-            free: data_u.iter().filter(|&x| *x == 0).count(),
-        }
-    }
-}
+/// Moves of every line, indexed by its 16 bits. Built by the compiler.
+#[allow(long_running_const_eval)]
+pub static TABLE: ([RowData; 65536], [ColData; 65536]) = calc_table();
 
-fn calc_line(data: &[i32; 4]) -> (RowData, ColData) {
-    let data_l = calc_line_l(data);
-    let data_r = calc_line_r(data);
-    (
-        RowData::new(&data_r, &data_l),
-        ColData::new(&data_l, &data_r),
-    )
-}
-
-pub static TABLE: Lazy<(Vec<RowData>, Vec<ColData>)> = Lazy::new(|| {
-    let mut row_data: Vec<RowData> = Vec::with_capacity(65536);
-    let mut col_data: Vec<ColData> = Vec::with_capacity(65536);
-    for i in 0..=65535u16 {
-        let data = u16_to_data(i);
-        let (row, col) = calc_line(&data);
-        row_data.push(row);
-        col_data.push(col);
+const fn calc_table() -> ([RowData; 65536], [ColData; 65536]) {
+    // each line moved once, a move to the right is the reversed line moved
+    let mut lefts = [0u16; 65536];
+    let mut i = 0;
+    while i < 65536 {
+        lefts[i] = move_line(i as u16);
+        i += 1;
+    }
+    let mut row_data = [RowData {
+        right: 0,
+        left: 0,
+        free: 0,
+    }; 65536];
+    let mut col_data = [ColData {
+        up: 0,
+        down: 0,
+        free: 0,
+    }; 65536];
+    let mut i = 0;
+    while i < 65536 {
+        let left = lefts[i];
+        let right = reverse(lefts[reverse(i as u16) as usize]);
+        let free = free(left);
+        row_data[i] = RowData { right, left, free };
+        col_data[i] = ColData {
+            up: to_col(left),
+            down: to_col(right),
+            free,
+        };
+        i += 1;
     }
     (row_data, col_data)
-});
+}